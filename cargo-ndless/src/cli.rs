@@ -41,6 +41,9 @@ pub enum Command {
 	/// Compile the current package and send it to Firebird Emu
 	#[structopt(name = "run")]
 	Run(Run),
+	/// Convert a dump from ndless::profile to a Chrome trace
+	#[structopt(name = "trace")]
+	Trace(Trace),
 }
 
 #[derive(Debug, StructOpt)]
@@ -69,3 +72,13 @@ pub struct Run {
 	#[structopt(flatten)]
 	pub build_settings: Build,
 }
+
+#[derive(Debug, StructOpt)]
+pub struct Trace {
+	/// The profile dump copied from the calculator
+	#[structopt(name = "DUMP", parse(from_os_str))]
+	pub input: PathBuf,
+	/// Where to write the trace, by default the dump's name ending with .json
+	#[structopt(short, long, parse(from_os_str))]
+	pub output: Option<PathBuf>,
+}
@@ -18,6 +18,7 @@ mod cli;
 mod files;
 mod firebird;
 mod install;
+mod trace;
 
 #[derive(Clone, Debug, Default, Deserialize)]
 struct ZehnOptions {
@@ -226,5 +227,17 @@ fn inner_main() -> Result<bool> {
 				});
 			Ok(some_failure)
 		}
+		cli::Command::Trace(cli::Trace { input, output }) => {
+			let output = output.unwrap_or_else(|| {
+				let input = if input.extension().map_or(false, |ext| ext == "tns") {
+					input.with_extension("")
+				} else {
+					input.clone()
+				};
+				input.with_extension("json")
+			});
+			trace::convert(&input, &output)?;
+			Ok(false)
+		}
 	}
 }
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use log::info;
+use serde_json::{json, Value};
+
+/// The header written by `ndless::profile::dump`
+const DUMP_HEADER: &str = "ndless-profile 1";
+
+/// Converts a dump from `ndless::profile::dump` at `input` into Chrome trace
+/// JSON at `output`.
+pub fn convert(input: &Path, output: &Path) -> Result<()> {
+	info!("Converting {} to {}", input.display(), output.display());
+	let trace = to_trace(BufReader::new(File::open(input)?))
+		.with_context(|| format!("Couldn't convert {}", input.display()))?;
+	let mut writer = BufWriter::new(File::create(output)?);
+	serde_json::to_writer(&mut writer, &trace)?;
+	writer.flush()?;
+	Ok(())
+}
+
+/// Converts a dump from `ndless::profile::dump` into Chrome trace JSON.
+fn to_trace(dump: impl BufRead) -> Result<Value> {
+	let mut lines = dump.lines();
+	let header = lines.next().transpose()?.unwrap_or_default();
+	let ticks_per_second: f64 = match header.strip_prefix(DUMP_HEADER) {
+		Some(rest) => rest.trim().parse().context("Invalid ticks per second")?,
+		None => bail!("Not an ndless profile dump"),
+	};
+	let to_micros = |ticks: u64| ticks as f64 * 1_000_000. / ticks_per_second;
+	let mut names = HashMap::new();
+	let mut events = vec![];
+	for (line_number, line) in lines.enumerate() {
+		let line = line?;
+		let context = || format!("Invalid line {}: {}", line_number + 2, line);
+		let (kind, fields) = line.split_once(' ').unwrap_or((&line, ""));
+		match kind {
+			"s" => {
+				let (id, name) = fields.split_once(' ').unwrap_or((fields, ""));
+				let id: u32 = id.parse().with_context(context)?;
+				names.insert(id, name.to_string());
+			}
+			"e" => {
+				let fields = fields
+					.split(' ')
+					.map(str::parse)
+					.collect::<Result<Vec<u64>, _>>()
+					.with_context(context)?;
+				ensure!(fields.len() == 3, context());
+				let name = names.get(&(fields[0] as u32)).with_context(|| {
+					format!("Unknown span {} on line {}", fields[0], line_number + 2)
+				})?;
+				events.push(json!({
+					"name": name,
+					"ph": "X",
+					"ts": to_micros(fields[1]),
+					"dur": to_micros(fields[2]),
+					"pid": 1,
+					"tid": 1,
+				}));
+			}
+			"" => {}
+			_ => bail!(context()),
+		}
+	}
+	Ok(json!({ "traceEvents": events, "displayTimeUnit": "ms" }))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let dump = "ndless-profile 1 32768\n\
+			s 0 draw frame\n\
+			s 1 update\n\
+			e 0 32768 16384\n\
+			e 1 65536 328\n";
+		let trace = to_trace(dump.as_bytes()).unwrap();
+		assert_eq!(
+			trace,
+			json!({
+				"traceEvents": [
+					{
+						"name": "draw frame",
+						"ph": "X",
+						"ts": 1_000_000.,
+						"dur": 500_000.,
+						"pid": 1,
+						"tid": 1,
+					},
+					{
+						"name": "update",
+						"ph": "X",
+						"ts": 2_000_000.,
+						"dur": 10_009.765_625,
+						"pid": 1,
+						"tid": 1,
+					},
+				],
+				"displayTimeUnit": "ms",
+			})
+		);
+	}
+
+	#[test]
+	fn invalid_dumps() {
+		assert!(to_trace("not a dump\n".as_bytes()).is_err());
+		assert!(to_trace("ndless-profile 1 32768\ne\n".as_bytes()).is_err());
+		assert!(to_trace("ndless-profile 1 32768\ne 0 1\n".as_bytes()).is_err());
+		assert!(to_trace("ndless-profile 1 32768\ne 0 1 2\n".as_bytes()).is_err());
+		assert!(to_trace("ndless-profile 1 32768\nx 0\n".as_bytes()).is_err());
+	}
+}
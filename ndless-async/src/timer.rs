@@ -6,7 +6,7 @@
 //!
 //! Check out [`TimerListener`]'s documentation for more.
 use alloc::rc::Rc;
-use core::cell::{Cell, RefCell};
+use core::cell::RefCell;
 use core::fmt;
 use core::future::Future;
 use core::pin::Pin;
@@ -18,15 +18,25 @@ use futures_util::stream::Stream;
 use futures_util::task::{AtomicWaker, Context, Poll};
 use ndless::alloc::fmt::Formatter;
 use ndless::prelude::*;
-use ndless::timer::{configure_sleep, get_ticks, has_time_passed, Ticks, TICKS_PER_SECOND};
+use ndless::timer::{
+	get_ticks, has_time_passed, schedule_wakeup, Ticks, TimerHandle, TICKS_PER_SECOND,
+};
 
 use crate::select;
 
 struct TimerData {
-	at_tick: Cell<u32>,
+	handle: TimerHandle,
 	waker: AtomicWaker,
 }
 
+impl TimerData {
+	fn at_tick(&self) -> u32 {
+		self.handle
+			.deadline()
+			.expect("async timers are never cancelled")
+	}
+}
+
 /// Timer Listener
 ///
 /// Used to create [`Timer`]s, which may be `.await`ed to wait for a specific
@@ -41,22 +51,18 @@ impl TimerListener {
 		let mut timers = self.timers.borrow_mut();
 		timers.retain(|timer| Rc::strong_count(timer) > 1);
 		timers.iter().for_each(|timer| {
-			if has_time_passed(timer.at_tick.get()) {
+			if has_time_passed(timer.at_tick()) {
 				timer.waker.wake();
 			}
 		})
 	}
+	/// Programs the sleep timer for the next deadline. Deadlines are shared
+	/// with other [`TimerHandle`]s, so timers used outside of this listener
+	/// also wake up the calculator.
 	pub(crate) fn config_sleep(&self) {
-		let half_max = 2u32.pow(31);
 		let mut timers = self.timers.borrow_mut();
 		timers.retain(|timer| Rc::strong_count(timer) > 1);
-		if let Some(time) = timers
-			.iter()
-			.map(|timer| timer.at_tick.get().wrapping_sub(get_ticks()) % half_max)
-			.min()
-		{
-			configure_sleep(time);
-		}
+		schedule_wakeup();
 	}
 	/// Sleeps for the specified number of milliseconds. Problems will occur
 	/// when sleeping for more than 2^31/32768 seconds, which is about 18 hours.
@@ -82,7 +88,7 @@ impl TimerListener {
 	/// future, which is about 18 hours.
 	pub fn sleep_until(&self, ticks: u32) -> Timer {
 		let timer = Rc::new(TimerData {
-			at_tick: Cell::new(ticks),
+			handle: TimerHandle::at(ticks),
 			waker: AtomicWaker::new(),
 		});
 		let mut timers = self.timers.borrow_mut();
@@ -278,7 +284,7 @@ pub struct Timer(Rc<TimerData>);
 impl Timer {
 	/// Get the tick that this timer should fire at
 	pub fn at_tick(&self) -> u32 {
-		self.0.at_tick.get()
+		self.0.at_tick()
 	}
 	/// Reschedules this timer for the specified number of milliseconds.
 	/// Problems will occur when sleeping for more than 2^31/32768 seconds,
@@ -317,7 +323,7 @@ impl Timer {
 	/// If this timer has already triggered, it will trigger again after the
 	/// specified delay.
 	pub fn reschedule_at(&self, ticks: u32) {
-		self.0.at_tick.set(ticks);
+		self.0.handle.reschedule_at(ticks);
 	}
 }
 
@@ -6,6 +6,61 @@ use core::ptr;
 
 use cty::c_void;
 
+#[cfg(feature = "alloc-stats")]
+pub mod stats;
+#[cfg(feature = "tlsf")]
+pub mod tlsf;
+
+/// Returns the number of bytes allocated by the program and the size of the
+/// heap, when known, for out-of-memory reports
+#[cfg(feature = "oom-handler")]
+pub(crate) fn usage() -> (Option<usize>, Option<usize>) {
+	#[cfg(feature = "tlsf")]
+	let usage = (Some(tlsf::used()), Some(tlsf::capacity()));
+	#[cfg(not(feature = "tlsf"))]
+	let usage: (Option<usize>, Option<usize>) = (None, None);
+	#[cfg(feature = "alloc-stats")]
+	let usage = (Some(stats::stats().current), stats::soft_limit().or(usage.1));
+	usage
+}
+
+/// Wraps an allocator to call the
+/// [emergency handler][ndless::oom::set_emergency_handler] when an allocation
+/// fails, and retry if it asks to. Used by the global allocator.
+pub struct Retrying<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Retrying<A> {
+	#[inline]
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		retry(layout, || self.0.alloc(layout))
+	}
+
+	#[inline]
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		retry(layout, || self.0.alloc_zeroed(layout))
+	}
+
+	#[inline]
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		self.0.dealloc(ptr, layout)
+	}
+
+	#[inline]
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+		retry(new_layout, || self.0.realloc(ptr, layout, new_size))
+	}
+}
+
+fn retry(layout: Layout, mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+	loop {
+		let ptr = alloc();
+		if !ptr.is_null() || !ndless::oom::__retry(layout) {
+			return ptr;
+		}
+	}
+}
+
 /// This allows for dynamic allocation, which calls the C functions `calloc` and
 /// `free`.
 pub struct CAllocator;
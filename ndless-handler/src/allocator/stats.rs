@@ -0,0 +1,316 @@
+//! Allocation statistics, leak tracking and a soft memory limit, enabled by the
+//! `alloc-stats` feature. This wraps the global allocator, so every allocation
+//! is slightly slower.
+//!
+//! ```
+//! use ndless::prelude::*;
+//! use ndless_handler::allocator::stats;
+//!
+//! // Fail allocations before the OS runs out of memory, so that `try_reserve`
+//! // returns an error instead
+//! stats::set_soft_limit(Some(8 * 1024 * 1024));
+//! stats::report_leaks_at_exit(None);
+//!
+//! let stats = stats::stats();
+//! println!("{} bytes used, {} at most", stats.current, stats.peak);
+//! ```
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::{self, addr_of_mut};
+
+use ndless::interrupt;
+use ndless::io::{self, Write};
+use ndless::path::PathBuf;
+use ndless::prelude::*;
+
+/// The number of live allocations that can be tracked for leak reports
+pub const TRACKED_ALLOCATIONS: usize = 512;
+
+/// The number of frames between the allocator and the code that called
+/// `alloc`, skipped when capturing the caller
+#[cfg(feature = "alloc-callers")]
+const SKIPPED_FRAMES: usize = 1;
+
+/// Statistics of the allocator, returned by [`stats`]. Sizes are in bytes, as
+/// requested by the program, without the allocator's overhead.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash, Default)]
+pub struct Stats {
+	/// The total size of live allocations
+	pub current: usize,
+	/// The maximum of `current` since the program started
+	pub peak: usize,
+	/// The number of live allocations
+	pub live: usize,
+	/// The number of successful allocations since the program started
+	pub allocations: u32,
+	/// The number of allocations that failed, because of the soft limit or
+	/// because the OS ran out of memory
+	pub failed: u32,
+	/// The number of live allocations that couldn't be tracked for leak
+	/// reports, because more than [`TRACKED_ALLOCATIONS`] were live
+	pub untracked: usize,
+}
+
+impl fmt::Display for Stats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} bytes in {} allocations, peak {} bytes, {} allocated, {} failed",
+			self.current, self.live, self.peak, self.allocations, self.failed
+		)
+	}
+}
+
+/// A live allocation, returned by [`live_allocations`]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct Allocation {
+	pub address: usize,
+	pub size: usize,
+	/// The return address in the code that allocated, with the
+	/// `alloc-callers` feature. It may point to a function of `alloc` that
+	/// allocates for its caller, such as `RawVec::grow`.
+	pub caller: Option<usize>,
+}
+
+impl fmt::Display for Allocation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} bytes at {:#010x}", self.size, self.address)?;
+		if let Some(caller) = self.caller {
+			write!(f, ", allocated from {:#010x}", caller)?;
+		}
+		Ok(())
+	}
+}
+
+struct State {
+	stats: Stats,
+	limit: Option<usize>,
+	tracked: [Option<Allocation>; TRACKED_ALLOCATIONS],
+}
+
+static mut STATE: State = State {
+	stats: Stats {
+		current: 0,
+		peak: 0,
+		live: 0,
+		allocations: 0,
+		failed: 0,
+		untracked: 0,
+	},
+	limit: None,
+	tracked: [None; TRACKED_ALLOCATIONS],
+};
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+	interrupt::free(|_| f(unsafe { &mut *addr_of_mut!(STATE) }))
+}
+
+impl State {
+	/// Returns `false` if allocating `size` more bytes would exceed the limit.
+	fn fits(&mut self, size: usize) -> bool {
+		let fits = self.limit.map_or(true, |limit| {
+			self.stats.current.saturating_add(size) <= limit
+		});
+		if !fits {
+			self.stats.failed += 1;
+		}
+		fits
+	}
+
+	fn add(&mut self, address: usize, size: usize, caller: Option<usize>) {
+		let stats = &mut self.stats;
+		stats.current += size;
+		stats.peak = stats.peak.max(stats.current);
+		stats.live += 1;
+		stats.allocations = stats.allocations.wrapping_add(1);
+		let allocation = Allocation {
+			address,
+			size,
+			caller,
+		};
+		match self.tracked.iter_mut().find(|slot| slot.is_none()) {
+			Some(slot) => *slot = Some(allocation),
+			None => stats.untracked += 1,
+		}
+	}
+
+	fn remove(&mut self, address: usize, size: usize) {
+		self.stats.current -= size;
+		self.stats.live -= 1;
+		match self
+			.tracked
+			.iter_mut()
+			.find(|slot| matches!(slot, Some(allocation) if allocation.address == address))
+		{
+			Some(slot) => *slot = None,
+			None => self.stats.untracked -= 1,
+		}
+	}
+}
+
+/// Returns the current statistics of the allocator.
+pub fn stats() -> Stats {
+	with_state(|state| state.stats)
+}
+
+/// Makes allocations fail when the total size of live allocations would exceed
+/// `limit` bytes, or removes the limit with `None`, which is the default.
+///
+/// Failing allocations make fallible functions such as
+/// [`Vec::try_reserve`] return an error, so that the program can free caches
+/// or use less memory before the OS runs out of it. Other allocations call the
+/// out-of-memory handler.
+pub fn set_soft_limit(limit: Option<usize>) {
+	with_state(|state| state.limit = limit);
+}
+
+/// Returns the limit set with [`set_soft_limit`].
+pub fn soft_limit() -> Option<usize> {
+	with_state(|state| state.limit)
+}
+
+/// Returns the tracked live allocations. See [`Stats::untracked`] for
+/// allocations that couldn't be tracked.
+pub fn live_allocations() -> Vec<Allocation> {
+	// The result is an allocation too, which is tracked but not returned
+	let mut allocations: Vec<Allocation> = Vec::with_capacity(stats().live + 1);
+	let capacity = allocations.capacity();
+	let own_address = allocations.as_ptr() as usize;
+	with_state(|state| {
+		let tracked = state.tracked.iter().flatten();
+		allocations.extend(tracked.take(capacity));
+	});
+	allocations.retain(|allocation| allocation.address != own_address);
+	allocations
+}
+
+/// Writes the live allocations to `writer`, returning how many there are.
+///
+/// Allocations that the program keeps until it exits on purpose, such as
+/// leaked [`Box`]es, are reported too.
+pub fn report_leaks(mut writer: impl Write) -> io::Result<usize> {
+	let allocations = live_allocations();
+	let untracked = stats().untracked;
+	writeln!(
+		writer,
+		"{} allocations were not freed:",
+		allocations.len() + untracked
+	)?;
+	for allocation in &allocations {
+		writeln!(writer, "{}", allocation)?;
+	}
+	if untracked > 0 {
+		writeln!(writer, "{} more allocations were not tracked", untracked)?;
+	}
+	writer.flush()?;
+	Ok(allocations.len() + untracked)
+}
+
+/// Reports leaks with [`report_leaks`] when the program exits, to the file at
+/// `path`, or to stdout if `None`.
+///
+/// This uses [`process::at_exit`][ndless::process::at_exit], so it should be
+/// called before registering other exit hooks, whose allocations would be
+/// reported otherwise.
+pub fn report_leaks_at_exit(path: Option<PathBuf>) {
+	ndless::process::at_exit(move || {
+		let _ = match path {
+			Some(path) => ndless::fs::File::create(path).and_then(report_leaks),
+			None => report_leaks(io::stdout()),
+		};
+	});
+}
+
+/// Returns the return address of the code that called the allocator, by
+/// walking the frame pointers. The program must be built with
+/// `-C force-frame-pointers=yes`.
+#[cfg(feature = "alloc-callers")]
+#[inline(always)]
+fn caller() -> Option<usize> {
+	unsafe {
+		let mut frame: *const usize;
+		core::arch::asm!("mov {}, r11", out(reg) frame);
+		for _ in 0..SKIPPED_FRAMES {
+			if frame.is_null() {
+				return None;
+			}
+			frame = *frame as *const usize;
+		}
+		if frame.is_null() {
+			None
+		} else {
+			Some(*frame.add(1))
+		}
+	}
+}
+
+#[cfg(not(feature = "alloc-callers"))]
+#[inline(always)]
+fn caller() -> Option<usize> {
+	None
+}
+
+/// Wraps an allocator to record [`stats`], track live allocations and enforce
+/// the [soft limit][set_soft_limit]. Used as the global allocator with the
+/// `alloc-stats` feature.
+pub struct Instrumented<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Instrumented<A> {
+	#[inline]
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		self.record(layout.size(), caller(), || self.0.alloc(layout))
+	}
+
+	#[inline]
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		self.record(layout.size(), caller(), || self.0.alloc_zeroed(layout))
+	}
+
+	#[inline]
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		self.0.dealloc(ptr, layout);
+		with_state(|state| state.remove(ptr as usize, layout.size()));
+	}
+
+	#[inline]
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		let caller = caller();
+		let grow = new_size.saturating_sub(layout.size());
+		if !with_state(|state| state.fits(grow)) {
+			return ptr::null_mut();
+		}
+		let new_ptr = self.0.realloc(ptr, layout, new_size);
+		with_state(|state| {
+			if new_ptr.is_null() {
+				state.stats.failed += 1;
+			} else {
+				state.remove(ptr as usize, layout.size());
+				state.add(new_ptr as usize, new_size, caller);
+			}
+		});
+		new_ptr
+	}
+}
+
+impl<A> Instrumented<A> {
+	unsafe fn record(
+		&self,
+		size: usize,
+		caller: Option<usize>,
+		alloc: impl FnOnce() -> *mut u8,
+	) -> *mut u8 {
+		if !with_state(|state| state.fits(size)) {
+			return ptr::null_mut();
+		}
+		let ptr = alloc();
+		with_state(|state| {
+			if ptr.is_null() {
+				state.stats.failed += 1;
+			} else {
+				state.add(ptr as usize, size, caller);
+			}
+		});
+		ptr
+	}
+}
@@ -0,0 +1,171 @@
+//! An allocator managing one large arena with [`ndless_tlsf`], enabled by the
+//! `tlsf` feature. Allocations are faster than with the OS `malloc`, fragment
+//! memory less, and aligned allocations don't waste memory.
+//!
+//! The arena is taken from the OS at the first allocation, which happens when
+//! the program starts: it is as large as possible, up to 8 MiB, to leave some
+//! memory to the OS. Allocations that don't fit in the arena use the OS
+//! `malloc`, like [`CAllocator`]. The arena is given back to the OS when the
+//! program exits, even if it panics.
+//!
+//! ```
+//! use ndless::prelude::*;
+//! use ndless_handler::allocator::tlsf;
+//!
+//! println!("{} of {} bytes used", tlsf::used(), tlsf::capacity());
+//! ```
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, addr_of_mut};
+
+use cty::c_void;
+use ndless::interrupt;
+use ndless_tlsf::Tlsf;
+
+use super::CAllocator;
+
+/// The size of the arena that is tried first
+const MAX_ARENA: usize = 8 * 1024 * 1024;
+/// Smaller arenas aren't worth it, and the OS `malloc` is used instead
+const MIN_ARENA: usize = 256 * 1024;
+
+struct Heap {
+	tlsf: Tlsf,
+	/// The arena, or null if it couldn't be taken
+	arena: *mut c_void,
+	initialized: bool,
+	/// Whether the arena was given back to the OS
+	released: bool,
+}
+
+static mut HEAP: Heap = Heap {
+	tlsf: Tlsf::new(),
+	arena: ptr::null_mut(),
+	initialized: false,
+	released: false,
+};
+
+fn with_heap<R>(f: impl FnOnce(&mut Heap) -> R) -> R {
+	interrupt::free(|_| f(unsafe { &mut *addr_of_mut!(HEAP) }))
+}
+
+impl Heap {
+	/// Returns the allocator of the arena, taking it from the OS the first
+	/// time, or `None` if there is no arena.
+	fn tlsf(&mut self) -> Option<&mut Tlsf> {
+		if !self.initialized {
+			self.initialized = true;
+			self.arena = unsafe { take_arena(&mut self.tlsf) };
+			if !self.arena.is_null() {
+				ndless::__set_heap_release(release);
+			}
+		}
+		if self.arena.is_null() || self.released {
+			None
+		} else {
+			Some(&mut self.tlsf)
+		}
+	}
+}
+
+unsafe fn take_arena(tlsf: &mut Tlsf) -> *mut c_void {
+	let mut size = MAX_ARENA;
+	while size >= MIN_ARENA {
+		let arena = ndless_sys::malloc(size);
+		if !arena.is_null() {
+			tlsf.init(arena as *mut u8, size);
+			return arena;
+		}
+		size /= 2;
+	}
+	ptr::null_mut()
+}
+
+/// Gives the arena back to the OS. Memory in the arena isn't freed anymore,
+/// reallocating it fails, and new allocations use the OS `malloc`.
+fn release() {
+	with_heap(|heap| {
+		heap.released = true;
+		unsafe { ndless_sys::free(heap.arena) };
+	});
+}
+
+/// Returns the number of bytes allocated in the arena. Allocations made with
+/// the OS `malloc` aren't counted.
+pub fn used() -> usize {
+	with_heap(|heap| heap.tlsf.used())
+}
+
+/// Returns the number of bytes that may be allocated in the arena, or 0 if it
+/// couldn't be taken from the OS.
+pub fn capacity() -> usize {
+	with_heap(|heap| heap.tlsf.capacity())
+}
+
+/// The global allocator with the `tlsf` feature. See the
+/// [module-level documentation][self].
+pub struct TlsfAllocator;
+
+unsafe impl GlobalAlloc for TlsfAllocator {
+	#[inline]
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		match with_heap(|heap| heap.tlsf()?.allocate(layout)) {
+			Some(ptr) => ptr.as_ptr(),
+			None => CAllocator.alloc(layout),
+		}
+	}
+
+	#[inline]
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		match with_heap(|heap| heap.tlsf()?.allocate(layout)) {
+			Some(ptr) => {
+				ptr::write_bytes(ptr.as_ptr(), 0, layout.size());
+				ptr.as_ptr()
+			}
+			None => CAllocator.alloc_zeroed(layout),
+		}
+	}
+
+	#[inline]
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		let in_arena = with_heap(|heap| {
+			let in_arena = heap.tlsf.contains(ptr);
+			if in_arena && !heap.released {
+				heap.tlsf
+					.deallocate(ptr::NonNull::new_unchecked(ptr), layout);
+			}
+			in_arena
+		});
+		if !in_arena {
+			CAllocator.dealloc(ptr, layout);
+		}
+	}
+
+	#[inline]
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		let (in_arena, released) = with_heap(|heap| (heap.tlsf.contains(ptr), heap.released));
+		if !in_arena {
+			return CAllocator.realloc(ptr, layout, new_size);
+		}
+		if released {
+			// The arena was given back to the OS while exiting, so the old
+			// contents can't be read anymore
+			return ptr::null_mut();
+		}
+		let resized = with_heap(|heap| {
+			let tlsf = heap.tlsf()?;
+			tlsf.reallocate(ptr::NonNull::new_unchecked(ptr), layout, new_size)
+		});
+		if let Some(new_ptr) = resized {
+			return new_ptr.as_ptr();
+		}
+		// The arena is full, so move the allocation out of it
+		let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+		let new_ptr = CAllocator.alloc(new_layout);
+		if !new_ptr.is_null() {
+			ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+			self.dealloc(ptr, layout);
+		}
+		new_ptr
+	}
+}
@@ -4,18 +4,26 @@
 #![feature(panic_info_message)]
 extern crate alloc;
 
-use alloc::format;
 use alloc::string::ToString;
 
-use crate::allocator::CAllocator;
 use core::slice;
 
-mod allocator;
+pub mod allocator;
 
-#[cfg(feature = "eh-personality")]
+#[cfg(all(feature = "eh-personality", not(feature = "unwind")))]
 #[lang = "eh_personality"]
 extern "C" fn eh_personality() {}
 
+#[cfg(feature = "unwind")]
+#[lang = "eh_personality"]
+unsafe extern "C" fn eh_personality(
+	state: cty::c_int,
+	exception: *mut cty::c_void,
+	context: *mut cty::c_void,
+) -> cty::c_int {
+	ndless::panic::__personality(state, exception, context)
+}
+
 #[cfg(feature = "lang-start")]
 #[lang = "start"]
 fn lang_start<T: ndless::process::Termination + 'static>(
@@ -26,42 +34,39 @@ fn lang_start<T: ndless::process::Termination + 'static>(
 	unsafe {
 		ndless::__init(slice::from_raw_parts(argv as *const _, argc as usize));
 	}
-	main().report() as isize
+	ndless::__main(main) as isize
 }
 
 #[cfg(feature = "oom-handler")]
 #[alloc_error_handler]
-fn on_oom(_layout: core::alloc::Layout) -> ! {
-	unsafe {
-		ndless_sys::abort();
-	}
+fn on_oom(layout: core::alloc::Layout) -> ! {
+	let (used, heap) = allocator::usage();
+	ndless::oom::__out_of_memory(ndless::oom::OutOfMemory { layout, used, heap })
 }
 
 #[cfg(feature = "panic-handler")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-	{
-		let msg = match info.message() {
-			Some(err) => format!("An error occured: {}", err),
-			None => "An error occured!".to_string(),
-		};
-		let location = match info.location() {
-			Some(loc) => format!(
-				"In file {} at line {} column {}",
-				loc.file(),
-				loc.line(),
-				loc.column()
-			),
-			None => "".to_string(),
-		};
-		ndless::msg::msg("Error", &format!("{}\n{}", msg, location));
-	}
-	ndless::process::abort();
+	ndless::crash::__panic(ndless::crash::CrashInfo {
+		message: info.message().map(|message| message.to_string()),
+		location: info.location().map(Into::into),
+	})
 }
 
-#[cfg(feature = "allocator")]
+#[cfg(all(feature = "allocator", not(feature = "tlsf")))]
+type Allocator = allocator::CAllocator;
+
+#[cfg(feature = "tlsf")]
+type Allocator = allocator::tlsf::TlsfAllocator;
+
+#[cfg(all(feature = "allocator", not(feature = "alloc-stats")))]
+#[global_allocator]
+static A: allocator::Retrying<Allocator> = allocator::Retrying(Allocator {});
+
+#[cfg(all(feature = "allocator", feature = "alloc-stats"))]
 #[global_allocator]
-static A: CAllocator = CAllocator;
+static A: allocator::Retrying<allocator::stats::Instrumented<Allocator>> =
+	allocator::Retrying(allocator::stats::Instrumented(Allocator {}));
 
 #[cfg(feature = "ctype-ptr")]
 #[no_mangle]
@@ -1,19 +1,131 @@
+#![allow(clippy::tabs_in_doc_comments)]
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
 
-use proc_macro2::Span;
+use proc_macro2::{Ident, TokenStream as TokenStream2};
 use quote::quote;
 use syn::parse_macro_input;
-use syn::{parse, spanned::Spanned, ItemFn};
+use syn::{
+	parse, spanned::Spanned, AttributeArgs, Data, DataStruct, DeriveInput, Fields, FnArg,
+	GenericArgument, ItemFn, Lit, LitInt, Meta, MetaNameValue, NestedMeta, PathArguments, Type,
+};
 
+/// Options of [`entry`]
+#[derive(Default)]
+struct Options {
+	cpu: Option<TokenStream2>,
+	lcd: Option<TokenStream2>,
+	resident: bool,
+	min_ndless_rev: Option<LitInt>,
+	no_scr_redraw: bool,
+}
+
+impl Options {
+	fn parse(args: AttributeArgs) -> parse::Result<Self> {
+		let mut options = Options::default();
+		for arg in args {
+			match arg {
+				NestedMeta::Meta(Meta::Path(path)) if path.is_ident("resident") => {
+					options.resident = true;
+				}
+				NestedMeta::Meta(Meta::Path(path)) if path.is_ident("no_scr_redraw") => {
+					options.no_scr_redraw = true;
+				}
+				NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
+					if path.is_ident("cpu") {
+						options.cpu = Some(cpu_speed(&lit)?);
+					} else if path.is_ident("lcd") {
+						options.lcd = Some(lcd_type(&lit)?);
+					} else if path.is_ident("min_ndless_rev") {
+						match lit {
+							Lit::Int(rev) => options.min_ndless_rev = Some(rev),
+							lit => {
+								return Err(parse::Error::new(
+									lit.span(),
+									"expected a revision, such as `min_ndless_rev = 2000`",
+								))
+							}
+						}
+					} else {
+						return Err(parse::Error::new(path.span(), "unknown option"));
+					}
+				}
+				arg => return Err(parse::Error::new(arg.span(), "unknown option")),
+			}
+		}
+		Ok(options)
+	}
+}
+
+fn cpu_speed(lit: &Lit) -> parse::Result<TokenStream2> {
+	let speed = match lit {
+		Lit::Str(speed) => speed.value(),
+		Lit::Int(speed) => speed.base10_digits().into(),
+		_ => String::new(),
+	};
+	let speed = match speed.as_str() {
+		"fast" | "150" => quote!(Mhz150),
+		"120" => quote!(Mhz120),
+		"normal" | "90" => quote!(Mhz90),
+		_ => {
+			return Err(parse::Error::new(
+				lit.span(),
+				"expected \"fast\", \"normal\", 150, 120 or 90",
+			))
+		}
+	};
+	Ok(quote!(::ndless::hw::cpu::CpuSpeed::#speed))
+}
+
+fn lcd_type(lit: &Lit) -> parse::Result<TokenStream2> {
+	let mode = match lit {
+		Lit::Str(mode) => mode.value(),
+		_ => String::new(),
+	};
+	let screen = match mode.as_str() {
+		"native" => return Ok(quote!(::ndless::hw::screen::lcd_type())),
+		"320x240x4" | "320x240x8" | "320x240x16" | "320x240x565" | "240x320x565"
+		| "320x240x555" | "240x320x555" => Ident::new(&format!("Screen{}", mode), lit.span()),
+		_ => {
+			return Err(parse::Error::new(
+				lit.span(),
+				"expected \"native\" or a mode such as \"320x240x565\"",
+			))
+		}
+	};
+	Ok(quote!(::ndless::hw::screen::Screen::#screen))
+}
+
+/// Marks the main function of the program, which may take the arguments of the
+/// program as [`Args`](https://docs.rs/ndless/*/ndless/env/type.Args.html):
+///
+/// ```ignore
+/// #[entry(cpu = "fast", lcd = "native", min_ndless_rev = 2000)]
+/// fn main(args: Args) {
+/// 	// Code
+/// }
+/// ```
+///
+/// Options configure the calculator before the function runs. Everything is
+/// restored when the program exits, even after a panic:
+/// - `cpu = "fast"`: sets the CPU speed. `"fast"` and `"normal"` are 150 and 90
+///   MHz, and `150`, `120` and `90` may be given directly.
+/// - `lcd = "native"`: switches the screen to its native mode, or to a mode
+///   such as `"320x240x565"`.
+/// - `resident`: keeps the program in memory when it exits, see
+///   `ndless::ndless::set_resident`.
+/// - `min_ndless_rev = 2000`: shows a message and exits if the installed Ndless
+///   is older than this revision.
+/// - `no_scr_redraw`: keeps what the program drew on the screen when it exits.
 #[proc_macro_attribute]
 pub fn entry(args: TokenStream, input: TokenStream) -> TokenStream {
 	let f = parse_macro_input!(input as ItemFn);
 	// check the function signature
 	let valid_signature = f.sig.constness.is_none()
 		&& f.sig.abi.is_none()
-		&& f.sig.inputs.is_empty()
+		&& f.sig.inputs.len() <= 1
+		&& !matches!(f.sig.inputs.first(), Some(FnArg::Receiver(_)))
 		&& f.sig.generics.params.is_empty()
 		&& f.sig.generics.where_clause.is_none()
 		&& f.sig.variadic.is_none();
@@ -27,31 +139,386 @@ pub fn entry(args: TokenStream, input: TokenStream) -> TokenStream {
 		.into();
 	}
 
-	if !args.is_empty() {
-		return parse::Error::new(Span::call_site(), "This attribute accepts no arguments")
-			.to_compile_error()
-			.into();
-	}
+	let options = match Options::parse(parse_macro_input!(args as AttributeArgs)) {
+		Ok(options) => options,
+		Err(err) => return err.to_compile_error().into(),
+	};
+
+	let rev_check = options
+		.min_ndless_rev
+		.map(|rev| quote!(::ndless::ndless::assert_ndless_rev(#rev);));
+	// Collected before `set_resident`, which forgets them
+	let (collect_args, call_args) = if f.sig.inputs.is_empty() {
+		(quote!(), quote!())
+	} else {
+		(
+			quote!(let main_args = ::ndless::env::args();),
+			quote!(main_args),
+		)
+	};
+	let resident = if options.resident {
+		quote!(::ndless::ndless::set_resident();)
+	} else {
+		quote!()
+	};
+	let cpu = options
+		.cpu
+		.map(|speed| quote!(::ndless::hw::cpu::set_speed(#speed);));
+	let lcd = options.lcd.map(|screen| {
+		quote!(
+			if !::ndless::hw::screen::set_lcd_type(#screen) {
+				::core::panic!("This screen mode isn't supported");
+			}
+		)
+	});
+	// Only cosmetic, so older versions of Ndless are fine
+	let no_scr_redraw = if options.no_scr_redraw {
+		quote!(let _ = ::ndless::ndless::no_scr_redraw();)
+	} else {
+		quote!()
+	};
 
 	let attrs = f.attrs;
+	let inputs = f.sig.inputs;
 	let stmts = f.block.stmts;
 	let ret = f.sig.output;
 	let name = f.sig.ident;
 	let unsafety = f.sig.unsafety;
 	let vis = f.vis;
+	let call = match unsafety {
+		Some(_) => quote!(unsafe { #name(#call_args) }),
+		None => quote!(#name(#call_args)),
+	};
 
 	quote!(
         #[export_name = "main"]
         unsafe fn __ndless_start(argc: ::ndless::cty::c_int, argv: *const *const ::ndless::cty::c_char) -> ::ndless::cty::c_int {
+            #rev_check
             let args: &[*const ::ndless::cty::c_char] = unsafe { ::core::slice::from_raw_parts(argv, argc as usize) };
 			::ndless::__init(args);
-			::ndless::process::Termination::report(#name())
+			::ndless::__main(|| {
+				#collect_args
+				#resident
+				#cpu
+				#lcd
+				#no_scr_redraw
+				#call
+			})
         }
 
         #(#attrs)*
-        #vis #unsafety fn #name() #ret {
+        #vis #unsafety fn #name(#inputs) #ret {
             #(#stmts)*
         }
     )
 		.into()
 }
+
+/// What a field of a [`FromArgs`] struct holds
+enum FieldKind {
+	/// An option, which is a flag if its type is `bool`
+	Option {
+		short: Option<char>,
+		long: Option<String>,
+		flag: bool,
+	},
+	Positional,
+	Document,
+}
+
+struct Field {
+	ident: Ident,
+	kind: FieldKind,
+	/// `Option` or `Vec`, if the type is one of them
+	wrapper: Option<String>,
+	ty: Type,
+	default: Option<TokenStream2>,
+}
+
+impl Field {
+	fn parse(field: &syn::Field) -> parse::Result<Self> {
+		let ident = field.ident.clone().unwrap();
+		let mut short = None;
+		let mut long = None;
+		let mut document = false;
+		let mut default = None;
+		for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("arg")) {
+			let list = match attr.parse_meta()? {
+				Meta::List(list) => list,
+				meta => return Err(parse::Error::new(meta.span(), "expected `#[arg(...)]`")),
+			};
+			for nested in list.nested {
+				match nested {
+					NestedMeta::Meta(Meta::Path(path)) if path.is_ident("short") => {
+						short = ident.to_string().chars().next();
+					}
+					NestedMeta::Meta(Meta::Path(path)) if path.is_ident("long") => {
+						long = Some(ident.to_string().replace('_', "-"));
+					}
+					NestedMeta::Meta(Meta::Path(path)) if path.is_ident("document") => {
+						document = true;
+					}
+					NestedMeta::Meta(Meta::NameValue(pair)) => {
+						let path = &pair.path;
+						match pair.lit {
+							Lit::Char(c) if path.is_ident("short") => short = Some(c.value()),
+							Lit::Str(name) if path.is_ident("long") => long = Some(name.value()),
+							Lit::Str(value) if path.is_ident("default") => {
+								default = Some(quote!(::core::convert::From::from(#value)));
+							}
+							lit if path.is_ident("default") => default = Some(quote!(#lit)),
+							lit => return Err(parse::Error::new(lit.span(), "unknown option")),
+						}
+					}
+					nested => return Err(parse::Error::new(nested.span(), "unknown option")),
+				}
+			}
+		}
+
+		let wrapper = wrapper(&field.ty);
+		let is_bool = matches!(&field.ty, Type::Path(path) if path.path.is_ident("bool"));
+		let kind = if document {
+			FieldKind::Document
+		} else if short.is_none() && long.is_none() {
+			FieldKind::Positional
+		} else {
+			FieldKind::Option {
+				short,
+				long,
+				flag: is_bool,
+			}
+		};
+		Ok(Field {
+			ident,
+			kind,
+			wrapper,
+			ty: field.ty.clone(),
+			default,
+		})
+	}
+
+	/// Returns how the argument is shown in error messages.
+	fn display_name(&self) -> String {
+		match &self.kind {
+			FieldKind::Option {
+				long: Some(long), ..
+			} => format!("--{}", long),
+			FieldKind::Option {
+				short: Some(short), ..
+			} => format!("-{}", short),
+			_ => format!("<{}>", self.ident),
+		}
+	}
+
+	fn is(&self, wrapper: &str) -> bool {
+		self.wrapper.as_deref() == Some(wrapper)
+	}
+
+	/// The type of the variable that the argument is parsed into
+	fn storage(&self) -> TokenStream2 {
+		let ty = &self.ty;
+		match (&self.kind, &self.wrapper) {
+			(FieldKind::Option { flag: true, .. }, _) | (_, Some(_)) => quote!(#ty),
+			_ => quote!(::core::option::Option<#ty>),
+		}
+	}
+
+	fn init(&self) -> TokenStream2 {
+		match self.kind {
+			FieldKind::Option { flag: true, .. } => quote!(false),
+			_ if self.is("Vec") => quote!(::ndless::alloc::vec::Vec::new()),
+			_ => quote!(::core::option::Option::None),
+		}
+	}
+
+	/// Stores `value` in the variable of the argument
+	fn store(&self, value: TokenStream2) -> TokenStream2 {
+		let ident = &self.ident;
+		if self.is("Vec") {
+			quote!(#ident.push(#value);)
+		} else {
+			quote!(#ident = ::core::option::Option::Some(#value);)
+		}
+	}
+
+	/// Returns the value of the field from its variable
+	fn finish(&self) -> TokenStream2 {
+		let ident = &self.ident;
+		let name = self.display_name();
+		match (&self.kind, &self.default) {
+			(FieldKind::Option { flag: true, .. }, _) | (FieldKind::Document, _) => quote!(#ident),
+			_ if self.wrapper.is_some() => quote!(#ident),
+			(_, Some(default)) => quote!(#ident.unwrap_or_else(|| #default)),
+			_ => quote!(#ident.ok_or_else(|| {
+				::ndless::args::Error::Missing(::core::convert::From::from(#name))
+			})?),
+		}
+	}
+}
+
+/// Returns the name of the wrapper for `Option<T>` and `Vec<T>`
+fn wrapper(ty: &Type) -> Option<String> {
+	let segment = match ty {
+		Type::Path(path) => path.path.segments.last()?,
+		_ => return None,
+	};
+	let name = segment.ident.to_string();
+	if name != "Option" && name != "Vec" {
+		return None;
+	}
+	match &segment.arguments {
+		PathArguments::AngleBracketed(args) => match args.args.first()? {
+			GenericArgument::Type(_) => Some(name),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+/// Derives `ndless::args::FromArgs` for a struct with named fields. See the
+/// documentation of the `ndless::args` module.
+#[proc_macro_derive(FromArgs, attributes(arg))]
+pub fn derive_from_args(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	match from_args(input) {
+		Ok(tokens) => tokens.into(),
+		Err(err) => err.to_compile_error().into(),
+	}
+}
+
+fn from_args(input: DeriveInput) -> parse::Result<TokenStream2> {
+	let fields = match &input.data {
+		Data::Struct(DataStruct {
+			fields: Fields::Named(fields),
+			..
+		}) => &fields.named,
+		_ => {
+			return Err(parse::Error::new(
+				input.span(),
+				"`FromArgs` can only be derived for structs with named fields",
+			))
+		}
+	};
+	if !input.generics.params.is_empty() {
+		return Err(parse::Error::new(
+			input.generics.span(),
+			"`FromArgs` can't be derived for generic structs",
+		));
+	}
+	let fields = fields
+		.iter()
+		.map(Field::parse)
+		.collect::<parse::Result<Vec<_>>>()?;
+
+	let variables = fields.iter().map(|field| {
+		let ident = &field.ident;
+		let storage = field.storage();
+		let init = field.init();
+		quote!(let mut #ident: #storage = #init;)
+	});
+	let document = fields
+		.iter()
+		.find(|field| matches!(field.kind, FieldKind::Document))
+		.map(|field| {
+			let ident = &field.ident;
+			quote!(#ident = parser.take_document();)
+		});
+	let options = fields.iter().filter_map(|field| {
+		let ident = &field.ident;
+		let (short, long, flag) = match &field.kind {
+			FieldKind::Option { short, long, flag } => (short, long, *flag),
+			_ => return None,
+		};
+		let short = option_tokens(short.map(|short| quote!(#short)));
+		let long = option_tokens(long.as_ref().map(|long| quote!(#long)));
+		let action = if flag {
+			quote!(#ident = true;)
+		} else {
+			field.store(quote!(parser.parse_value()?))
+		};
+		Some(quote!(
+			if arg.is(#short, #long) {
+				#action
+				continue;
+			}
+		))
+	});
+
+	let positionals: Vec<_> = fields
+		.iter()
+		.filter(|field| matches!(field.kind, FieldKind::Positional))
+		.collect();
+	// A `Vec` takes all the remaining values, so nothing may come after it
+	if let Some(field) = positionals
+		.split_last()
+		.and_then(|(_, rest)| rest.iter().find(|field| field.is("Vec")))
+	{
+		return Err(parse::Error::new(
+			field.ident.span(),
+			"a `Vec` positional argument must be the last positional argument",
+		));
+	}
+	let positional = if positionals.is_empty() {
+		quote!()
+	} else {
+		let arms = positionals.iter().enumerate().map(|(index, field)| {
+			let name = field.display_name();
+			let store = field.store(quote!(::ndless::args::parse_arg(#name, value)?));
+			if field.is("Vec") {
+				quote!(_ => { #store })
+			} else {
+				quote!(#index => { #store })
+			}
+		});
+		let rest = if positionals.last().is_some_and(|field| field.is("Vec")) {
+			quote!()
+		} else {
+			quote!(_ => return ::core::result::Result::Err(arg.unexpected()),)
+		};
+		quote!(
+			if let ::ndless::args::Arg::Value(value) = &arg {
+				match positional {
+					#(#arms,)*
+					#rest
+				}
+				positional += 1;
+				continue;
+			}
+		)
+	};
+	let positional_counter = if positionals.is_empty() {
+		quote!()
+	} else {
+		quote!(let mut positional = 0usize;)
+	};
+
+	let name = &input.ident;
+	let idents = fields.iter().map(|field| &field.ident);
+	let finish = fields.iter().map(Field::finish);
+	Ok(quote!(
+		impl ::ndless::args::FromArgs for #name {
+			fn from_parser(
+				parser: &mut ::ndless::args::Parser,
+			) -> ::core::result::Result<Self, ::ndless::args::Error> {
+				#(#variables)*
+				#document
+				#positional_counter
+				while let ::core::option::Option::Some(arg) = parser.next()? {
+					#(#options)*
+					#positional
+					return ::core::result::Result::Err(arg.unexpected());
+				}
+				::core::result::Result::Ok(#name {
+					#(#idents: #finish,)*
+				})
+			}
+		}
+	))
+}
+
+fn option_tokens(tokens: Option<TokenStream2>) -> TokenStream2 {
+	match tokens {
+		Some(tokens) => quote!(::core::option::Option::Some(#tokens)),
+		None => quote!(::core::option::Option::None),
+	}
+}
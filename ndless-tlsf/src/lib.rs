@@ -0,0 +1,612 @@
+//! # ndless-tlsf
+//! A [Two-Level Segregated Fit](http://www.gii.upv.es/tlsf/) allocator, which
+//! manages a single region of memory given to [`Tlsf::init`].
+//!
+//! Free blocks are kept in lists segregated by size: a first level of powers of
+//! two, each split into 16 linearly spaced second-level classes. Bitmaps of the
+//! non-empty lists find a suitable block in constant time, and freed blocks
+//! are merged with their free neighbours immediately, which keeps
+//! fragmentation low.
+//!
+//! Each block has a header of two words. Blocks are aligned to two words, and
+//! larger alignments are handled by splitting off the start of a bigger block.
+//!
+//! ```
+//! use core::alloc::Layout;
+//! use ndless_tlsf::Tlsf;
+//!
+//! let mut memory = vec![0u8; 64 * 1024];
+//! let mut tlsf = Tlsf::new();
+//! unsafe { tlsf.init(memory.as_mut_ptr(), memory.len()) };
+//!
+//! let layout = Layout::from_size_align(100, 64).unwrap();
+//! let ptr = tlsf.allocate(layout).unwrap();
+//! assert_eq!(ptr.as_ptr() as usize % 64, 0);
+//! unsafe { tlsf.deallocate(ptr, layout) };
+//! ```
+#![cfg_attr(not(test), no_std)]
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+
+/// The alignment of blocks, which is also the size of their header and their
+/// minimum size, to hold the free list pointers
+const ALIGN: usize = 2 * size_of::<usize>();
+const ALIGN_LOG2: usize = ALIGN.trailing_zeros() as usize;
+const HEADER: usize = ALIGN;
+
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+/// Blocks smaller than this are all in the first first-level class, in
+/// second-level classes `ALIGN` bytes apart
+const FL_SHIFT: usize = SL_LOG2 + ALIGN_LOG2;
+const SMALL_BLOCK: usize = 1 << FL_SHIFT;
+const FL_COUNT: usize = usize::BITS as usize - FL_SHIFT + 1;
+
+/// Flags stored in the low bits of the size of blocks
+const FREE: usize = 1;
+const PREV_FREE: usize = 2;
+const FLAGS: usize = FREE | PREV_FREE;
+
+/// A block of memory, preceded in memory by the block at `prev_phys` and
+/// followed by the one at [`next_phys`]. The free list pointers are part of the
+/// payload, so they are only valid when the block is free, and `prev_phys` is
+/// only valid when the previous block is free.
+#[repr(C)]
+struct Block {
+	prev_phys: *mut Block,
+	size: usize,
+	next_free: *mut Block,
+	prev_free: *mut Block,
+}
+
+unsafe fn size(block: *mut Block) -> usize {
+	(*block).size & !FLAGS
+}
+
+unsafe fn is_free(block: *mut Block) -> bool {
+	(*block).size & FREE != 0
+}
+
+unsafe fn is_prev_free(block: *mut Block) -> bool {
+	(*block).size & PREV_FREE != 0
+}
+
+unsafe fn payload(block: *mut Block) -> *mut u8 {
+	(block as *mut u8).add(HEADER)
+}
+
+unsafe fn from_payload(ptr: *mut u8) -> *mut Block {
+	ptr.sub(HEADER) as *mut Block
+}
+
+unsafe fn next_phys(block: *mut Block) -> *mut Block {
+	payload(block).add(size(block)) as *mut Block
+}
+
+fn align_up(value: usize, align: usize) -> Option<usize> {
+	Some(value.checked_add(align - 1)? & !(align - 1))
+}
+
+/// Returns the size of the block needed for `size` bytes.
+fn adjust(size: usize) -> Option<usize> {
+	Some(align_up(size, ALIGN)?.max(ALIGN))
+}
+
+fn log2(value: usize) -> usize {
+	(usize::BITS - 1 - value.leading_zeros()) as usize
+}
+
+/// Returns the first- and second-level class of blocks of `size` bytes.
+fn mapping(size: usize) -> (usize, usize) {
+	if size < SMALL_BLOCK {
+		(0, size >> ALIGN_LOG2)
+	} else {
+		let fl = log2(size);
+		let sl = (size >> (fl - SL_LOG2)) ^ SL_COUNT;
+		(fl - FL_SHIFT + 1, sl)
+	}
+}
+
+/// Returns the first class whose blocks are all at least `size` bytes.
+fn mapping_search(size: usize) -> Option<(usize, usize)> {
+	if size < SMALL_BLOCK {
+		Some(mapping(size))
+	} else {
+		let round = (1 << (log2(size) - SL_LOG2)) - 1;
+		Some(mapping(size.checked_add(round)?))
+	}
+}
+
+/// A TLSF allocator for a single region of memory. See the
+/// [crate-level documentation][crate].
+pub struct Tlsf {
+	fl_bitmap: usize,
+	sl_bitmaps: [usize; FL_COUNT],
+	heads: [[*mut Block; SL_COUNT]; FL_COUNT],
+	start: usize,
+	end: usize,
+	used: usize,
+	capacity: usize,
+}
+
+unsafe impl Send for Tlsf {}
+
+impl Default for Tlsf {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Tlsf {
+	/// Creates an allocator without any memory. Use [`init`][Tlsf::init] to
+	/// give it a region.
+	pub const fn new() -> Self {
+		Tlsf {
+			fl_bitmap: 0,
+			sl_bitmaps: [0; FL_COUNT],
+			heads: [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+			start: 0,
+			end: 0,
+			used: 0,
+			capacity: 0,
+		}
+	}
+
+	/// Gives the `len` bytes at `memory` to the allocator. Regions too small
+	/// to hold a block are ignored.
+	///
+	/// # Safety
+	///
+	/// The memory must be valid for reads and writes, and not be used for
+	/// anything else until the allocator is dropped. This may only be called
+	/// once.
+	pub unsafe fn init(&mut self, memory: *mut u8, len: usize) {
+		let start = match align_up(memory as usize, ALIGN) {
+			Some(start) => start,
+			None => return,
+		};
+		let end = (memory as usize).saturating_add(len) & !(ALIGN - 1);
+		if end < start || end - start < 2 * HEADER + ALIGN {
+			return;
+		}
+		let block = start as *mut Block;
+		(*block).prev_phys = ptr::null_mut();
+		(*block).size = (end - start - 2 * HEADER) | FREE;
+		// A used block of size 0 marks the end of the region
+		let sentinel = next_phys(block);
+		(*sentinel).prev_phys = block;
+		(*sentinel).size = PREV_FREE;
+		self.insert(block);
+		self.start = start;
+		self.end = end;
+		self.capacity = size(block);
+	}
+
+	/// Returns `true` if `ptr` is in the region managed by the allocator.
+	pub fn contains(&self, ptr: *const u8) -> bool {
+		(self.start..self.end).contains(&(ptr as usize))
+	}
+
+	/// Returns the number of bytes in allocated blocks, excluding headers.
+	pub fn used(&self) -> usize {
+		self.used
+	}
+
+	/// Returns the number of bytes that may be allocated when nothing is
+	/// allocated.
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	unsafe fn insert(&mut self, block: *mut Block) {
+		let (fl, sl) = mapping(size(block));
+		let head = self.heads[fl][sl];
+		(*block).next_free = head;
+		(*block).prev_free = ptr::null_mut();
+		if !head.is_null() {
+			(*head).prev_free = block;
+		}
+		self.heads[fl][sl] = block;
+		self.fl_bitmap |= 1 << fl;
+		self.sl_bitmaps[fl] |= 1 << sl;
+	}
+
+	unsafe fn remove(&mut self, block: *mut Block) {
+		let (fl, sl) = mapping(size(block));
+		let (next, prev) = ((*block).next_free, (*block).prev_free);
+		if !next.is_null() {
+			(*next).prev_free = prev;
+		}
+		if prev.is_null() {
+			self.heads[fl][sl] = next;
+			if next.is_null() {
+				self.sl_bitmaps[fl] &= !(1 << sl);
+				if self.sl_bitmaps[fl] == 0 {
+					self.fl_bitmap &= !(1 << fl);
+				}
+			}
+		} else {
+			(*prev).next_free = next;
+		}
+	}
+
+	/// Finds a free block of at least `size` bytes.
+	fn find(&self, size: usize) -> Option<*mut Block> {
+		let (mut fl, sl) = mapping_search(size)?;
+		if fl >= FL_COUNT {
+			return None;
+		}
+		let mut sl_map = self.sl_bitmaps[fl] & (!0 << sl);
+		if sl_map == 0 {
+			let fl_map = self.fl_bitmap & (!0usize).checked_shl(fl as u32 + 1).unwrap_or(0);
+			if fl_map == 0 {
+				return None;
+			}
+			fl = fl_map.trailing_zeros() as usize;
+			sl_map = self.sl_bitmaps[fl];
+		}
+		Some(self.heads[fl][sl_map.trailing_zeros() as usize])
+	}
+
+	/// Marks the free `block`, already removed from its list, as used.
+	unsafe fn mark_used(&mut self, block: *mut Block) {
+		(*block).size &= !FREE;
+		(*next_phys(block)).size &= !PREV_FREE;
+		self.used += size(block);
+	}
+
+	/// Frees the rest of the used `block` after `size` bytes, if it is big
+	/// enough to be a block.
+	unsafe fn shrink(&mut self, block: *mut Block, size: usize) {
+		let total = self::size(block);
+		if total < size + HEADER + ALIGN {
+			return;
+		}
+		let rest = payload(block).add(size) as *mut Block;
+		(*rest).prev_phys = block;
+		(*rest).size = total - size - HEADER;
+		(*block).size = size | ((*block).size & PREV_FREE);
+		(*next_phys(rest)).prev_phys = rest;
+		self.used -= total - size;
+		self.free(rest);
+	}
+
+	/// Marks `block` as free, merges it with its free neighbours, and inserts
+	/// the result in the free lists.
+	unsafe fn free(&mut self, mut block: *mut Block) {
+		(*block).size |= FREE;
+		if is_prev_free(block) {
+			let prev = (*block).prev_phys;
+			self.remove(prev);
+			(*prev).size += HEADER + size(block);
+			block = prev;
+		}
+		let next = next_phys(block);
+		if is_free(next) {
+			self.remove(next);
+			(*block).size += HEADER + size(next);
+		}
+		let next = next_phys(block);
+		(*next).prev_phys = block;
+		(*next).size |= PREV_FREE;
+		self.insert(block);
+	}
+
+	/// Allocates memory for `layout`, or returns `None` if there isn't a big
+	/// enough free block.
+	pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+		let size = adjust(layout.size())?;
+		let align = layout.align();
+		unsafe {
+			let block = if align <= ALIGN {
+				let block = self.find(size)?;
+				self.remove(block);
+				block
+			} else {
+				// Room to split off a free block before the aligned address
+				let search = size.checked_add(align)?.checked_add(HEADER + ALIGN)?;
+				let block = self.find(search)?;
+				self.remove(block);
+				self.split_aligned(block, align)
+			};
+			self.mark_used(block);
+			self.shrink(block, size);
+			Some(NonNull::new_unchecked(payload(block)))
+		}
+	}
+
+	/// Splits the start of the free `block` into another free block, so that
+	/// the payload of the returned block is aligned to `align`.
+	unsafe fn split_aligned(&mut self, block: *mut Block, align: usize) -> *mut Block {
+		let start = payload(block) as usize;
+		let mut aligned = align_up(start, align).unwrap();
+		if aligned == start {
+			return block;
+		}
+		if aligned - start < HEADER + ALIGN {
+			aligned += align;
+		}
+		let gap = aligned - start;
+		let aligned_block = (aligned - HEADER) as *mut Block;
+		(*aligned_block).prev_phys = block;
+		(*aligned_block).size = (size(block) - gap) | FREE | PREV_FREE;
+		(*next_phys(aligned_block)).prev_phys = aligned_block;
+		(*block).size = (gap - HEADER) | FREE | ((*block).size & PREV_FREE);
+		self.insert(block);
+		aligned_block
+	}
+
+	/// Frees memory returned by [`allocate`][Tlsf::allocate].
+	///
+	/// # Safety
+	///
+	/// `ptr` must have been allocated by this allocator, and not freed yet.
+	pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, _layout: Layout) {
+		let block = from_payload(ptr.as_ptr());
+		self.used -= size(block);
+		self.free(block);
+	}
+
+	/// Changes the size of the allocation at `ptr` to `new_size`, keeping its
+	/// alignment and contents. The allocation is resized in place when
+	/// possible, or moved otherwise. Returns `None` if there isn't enough
+	/// memory, in which case the allocation is unchanged.
+	///
+	/// # Safety
+	///
+	/// `ptr` must have been allocated by this allocator with `layout`, and not
+	/// freed yet.
+	pub unsafe fn reallocate(
+		&mut self,
+		ptr: NonNull<u8>,
+		layout: Layout,
+		new_size: usize,
+	) -> Option<NonNull<u8>> {
+		let block = from_payload(ptr.as_ptr());
+		let new = adjust(new_size)?;
+		let current = size(block);
+		if new <= current {
+			self.shrink(block, new);
+			return Some(ptr);
+		}
+		let next = next_phys(block);
+		if is_free(next) && current + HEADER + size(next) >= new {
+			self.remove(next);
+			let grown = HEADER + size(next);
+			(*block).size += grown;
+			(*next_phys(block)).size &= !PREV_FREE;
+			self.used += grown;
+			self.shrink(block, new);
+			return Some(ptr);
+		}
+		let new_layout = Layout::from_size_align(new_size, layout.align()).ok()?;
+		let new_ptr = self.allocate(new_layout)?;
+		ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), layout.size().min(new_size));
+		self.deallocate(ptr, layout);
+		Some(new_ptr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Memory for an allocator, aligned to 4096 bytes
+	#[repr(C, align(4096))]
+	struct Page([u8; 4096]);
+
+	struct Heap {
+		_memory: Vec<Page>,
+		tlsf: Box<Tlsf>,
+	}
+
+	fn heap(pages: usize) -> Heap {
+		let mut memory: Vec<Page> = (0..pages).map(|_| Page([0; 4096])).collect();
+		let mut tlsf = Box::new(Tlsf::new());
+		unsafe { tlsf.init(memory.as_mut_ptr() as *mut u8, pages * 4096) };
+		Heap {
+			_memory: memory,
+			tlsf,
+		}
+	}
+
+	fn layout(size: usize, align: usize) -> Layout {
+		Layout::from_size_align(size, align).unwrap()
+	}
+
+	fn fill(ptr: NonNull<u8>, size: usize, value: u8) {
+		unsafe { ptr::write_bytes(ptr.as_ptr(), value, size) };
+	}
+
+	fn check(ptr: NonNull<u8>, size: usize, value: u8) {
+		let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), size) };
+		assert!(bytes.iter().all(|&byte| byte == value));
+	}
+
+	/// Checks that all the memory is free and merged into a single block.
+	fn assert_empty(tlsf: &mut Tlsf) {
+		assert_eq!(tlsf.used(), 0);
+		let block = tlsf.start as *mut Block;
+		unsafe {
+			assert!(is_free(block));
+			assert_eq!(size(block), tlsf.capacity());
+			let (fl, sl) = mapping(size(block));
+			assert_eq!(tlsf.heads[fl][sl], block);
+			assert!((*block).next_free.is_null());
+		}
+		assert_eq!(tlsf.fl_bitmap.count_ones(), 1);
+	}
+
+	#[test]
+	fn mapping_search_rounds_up() {
+		for size in (ALIGN..1 << 20).step_by(ALIGN) {
+			let (fl, sl) = mapping_search(size).unwrap();
+			let (min_fl, min_sl) = mapping(size);
+			assert!((fl, sl) >= (min_fl, min_sl));
+			// The smallest block of the class must fit `size`
+			let smallest = if fl == 0 {
+				sl << ALIGN_LOG2
+			} else {
+				(SL_COUNT + sl) << (fl + FL_SHIFT - 1 - SL_LOG2)
+			};
+			assert!(
+				smallest >= size,
+				"{} is in a class starting at {}",
+				size,
+				smallest
+			);
+		}
+	}
+
+	#[test]
+	fn allocate_and_free() {
+		let mut heap = heap(4);
+		let tlsf = &mut heap.tlsf;
+		assert_eq!(tlsf.capacity(), 4 * 4096 - 2 * HEADER);
+		let sizes = [1, 8, 24, 100, 1000, 3000];
+		let ptrs: Vec<_> = sizes
+			.iter()
+			.enumerate()
+			.map(|(i, &size)| {
+				let ptr = tlsf.allocate(layout(size, 1)).unwrap();
+				assert_eq!(ptr.as_ptr() as usize % ALIGN, 0);
+				assert!(tlsf.contains(ptr.as_ptr()));
+				fill(ptr, size, i as u8);
+				ptr
+			})
+			.collect();
+		for (i, (&ptr, &size)) in ptrs.iter().zip(&sizes).enumerate() {
+			check(ptr, size, i as u8);
+		}
+		// Free in a different order, to merge with both neighbours
+		for &i in &[1, 3, 2, 0, 5, 4] {
+			unsafe { tlsf.deallocate(ptrs[i], layout(sizes[i], 1)) };
+		}
+		assert_empty(tlsf);
+	}
+
+	#[test]
+	fn aligned_allocations() {
+		let mut heap = heap(16);
+		let tlsf = &mut heap.tlsf;
+		let mut ptrs = vec![];
+		for shift in 0..13 {
+			let layout = layout(100, 1 << shift);
+			let ptr = tlsf.allocate(layout).unwrap();
+			assert_eq!(ptr.as_ptr() as usize % (1 << shift), 0);
+			fill(ptr, 100, shift);
+			ptrs.push((ptr, layout));
+		}
+		for (shift, &(ptr, layout)) in ptrs.iter().enumerate() {
+			check(ptr, 100, shift as u8);
+			unsafe { tlsf.deallocate(ptr, layout) };
+		}
+		assert_empty(tlsf);
+	}
+
+	#[test]
+	fn exhaustion() {
+		let mut heap = heap(4);
+		let tlsf = &mut heap.tlsf;
+		assert!(tlsf.allocate(layout(tlsf.capacity() + 1, 1)).is_none());
+		assert!(tlsf.allocate(layout(isize::MAX as usize, 1)).is_none());
+		let mut ptrs = vec![];
+		while let Some(ptr) = tlsf.allocate(layout(100, 1)) {
+			ptrs.push(ptr);
+		}
+		assert!(ptrs.len() > 100);
+		for ptr in ptrs {
+			unsafe { tlsf.deallocate(ptr, layout(100, 1)) };
+		}
+		assert_empty(tlsf);
+	}
+
+	#[test]
+	fn reallocate_in_place() {
+		let mut heap = heap(4);
+		let tlsf = &mut heap.tlsf;
+		let ptr = tlsf.allocate(layout(64, 8)).unwrap();
+		fill(ptr, 64, 7);
+		let grown = unsafe { tlsf.reallocate(ptr, layout(64, 8), 1000) }.unwrap();
+		assert_eq!(grown, ptr);
+		check(grown, 64, 7);
+		let shrunk = unsafe { tlsf.reallocate(grown, layout(1000, 8), 32) }.unwrap();
+		assert_eq!(shrunk, ptr);
+		check(shrunk, 32, 7);
+		assert_eq!(tlsf.used(), adjust(32).unwrap());
+		unsafe { tlsf.deallocate(shrunk, layout(32, 8)) };
+		assert_empty(tlsf);
+	}
+
+	#[test]
+	fn reallocate_moves() {
+		let mut heap = heap(4);
+		let tlsf = &mut heap.tlsf;
+		let ptr = tlsf.allocate(layout(64, 256)).unwrap();
+		// Too big for the gap before `ptr`, so it is right after it
+		let blocker = tlsf.allocate(layout(300, 1)).unwrap();
+		fill(ptr, 64, 3);
+		let moved = unsafe { tlsf.reallocate(ptr, layout(64, 256), 2000) }.unwrap();
+		assert_ne!(moved, ptr);
+		assert_eq!(moved.as_ptr() as usize % 256, 0);
+		check(moved, 64, 3);
+		assert!(unsafe { tlsf.reallocate(moved, layout(2000, 256), 1 << 20) }.is_none());
+		check(moved, 64, 3);
+		unsafe {
+			tlsf.deallocate(moved, layout(2000, 256));
+			tlsf.deallocate(blocker, layout(300, 1));
+		}
+		assert_empty(tlsf);
+	}
+
+	#[test]
+	fn random_operations() {
+		let mut heap = heap(64);
+		let tlsf = &mut heap.tlsf;
+		let mut state = 0x2545_f491_u32;
+		let mut random = move |max: usize| {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			state as usize % max
+		};
+		let mut live: Vec<(NonNull<u8>, Layout, u8)> = vec![];
+		for i in 0..20_000 {
+			let value = i as u8;
+			match random(4) {
+				0 | 1 => {
+					let layout = layout(1 + random(2000), 1 << random(8));
+					if let Some(ptr) = tlsf.allocate(layout) {
+						assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+						fill(ptr, layout.size(), value);
+						live.push((ptr, layout, value));
+					}
+				}
+				2 if !live.is_empty() => {
+					let (ptr, layout, value) = live.swap_remove(random(live.len()));
+					check(ptr, layout.size(), value);
+					unsafe { tlsf.deallocate(ptr, layout) };
+				}
+				3 if !live.is_empty() => {
+					let index = random(live.len());
+					let (ptr, layout, old_value) = live[index];
+					let new_size = 1 + random(4000);
+					if let Some(new_ptr) = unsafe { tlsf.reallocate(ptr, layout, new_size) } {
+						assert_eq!(new_ptr.as_ptr() as usize % layout.align(), 0);
+						check(new_ptr, layout.size().min(new_size), old_value);
+						fill(new_ptr, new_size, value);
+						live[index] = (new_ptr, self::layout(new_size, layout.align()), value);
+					}
+				}
+				_ => {}
+			}
+		}
+		for (ptr, layout, value) in live {
+			check(ptr, layout.size(), value);
+			unsafe { tlsf.deallocate(ptr, layout) };
+		}
+		assert_empty(tlsf);
+	}
+}
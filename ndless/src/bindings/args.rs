@@ -0,0 +1,515 @@
+//! # Argument parsing
+//! This module parses the arguments of the program into a struct, with
+//! `#[derive(FromArgs)]`:
+//!
+//! ```
+//! use ndless::args::{self, FromArgs};
+//! use ndless::path::PathBuf;
+//!
+//! #[derive(FromArgs)]
+//! struct Options {
+//! 	/// `-v` or `--verbose`
+//! 	#[arg(short, long)]
+//! 	verbose: bool,
+//! 	/// `--speed 120`, `--speed=120` or nothing for 90
+//! 	#[arg(long, default = 90)]
+//! 	speed: u32,
+//! 	/// The first argument that isn't an option
+//! 	file: Option<PathBuf>,
+//! }
+//!
+//! let options: Options = args::parse();
+//! ```
+//!
+//! Fields with `short` or `long` are options, named after the field unless
+//! given a name, such as `short = 'x'` or `long = "name"`. Their type may be:
+//! - `bool`, for flags that don't take a value
+//! - `Option<T>`, for options that may be omitted
+//! - `Vec<T>`, for options that may be repeated
+//! - any other type implementing [`FromArg`], for options that are required
+//!   unless given a `default`
+//!
+//! Other fields are positional arguments, in order. They are required, unless
+//! their type is `Option<T>`, and a last field of type `Vec<T>` takes all the
+//! remaining ones. `--` ends options, so that the next arguments are positional
+//! even if they start with `-`.
+//!
+//! When the program is launched by opening a document whose extension is
+//! associated with it, the path of the document is the first positional
+//! argument. It can be told apart from arguments given by other programs with
+//! [`invocation`], or with an `Option<PathBuf>` field marked with
+//! `#[arg(document)]`, which receives it instead.
+//!
+//! For other uses, [`Parser`] splits arguments into options and values without
+//! a struct.
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::env;
+use crate::ffi::{OsStr, OsStrExt, OsString};
+use crate::path::PathBuf;
+use crate::prelude::*;
+
+pub use ndless_macros::FromArgs;
+
+/// How the program was launched, returned by [`invocation`]
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub enum Invocation {
+	/// Without arguments, e.g. from the OS document browser
+	Direct,
+	/// By opening a document whose extension is associated with the program,
+	/// see [`env::opened_document`]
+	Document(PathBuf),
+	/// With arguments given by another program, e.g. with
+	/// [`process::Command`][crate::process::Command]
+	Arguments,
+}
+
+/// Returns how the program was launched.
+pub fn invocation() -> Invocation {
+	if let Some(document) = env::opened_document() {
+		Invocation::Document(document)
+	} else if env::args_os().len() > 1 {
+		Invocation::Arguments
+	} else {
+		Invocation::Direct
+	}
+}
+
+/// An error while parsing arguments
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub enum Error {
+	/// An option that the program doesn't know, or too many positional
+	/// arguments
+	Unexpected(String),
+	/// An option that doesn't take a value was given one with `=`
+	UnexpectedValue(String),
+	/// An option that takes a value was the last argument
+	MissingValue(String),
+	/// A required option or positional argument wasn't given
+	Missing(String),
+	/// A value couldn't be parsed
+	InvalidValue { arg: String, message: String },
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Unexpected(arg) => write!(f, "Unexpected argument {}", arg),
+			Error::UnexpectedValue(option) => write!(f, "{} doesn't take a value", option),
+			Error::MissingValue(option) => write!(f, "{} needs a value", option),
+			Error::Missing(arg) => write!(f, "{} is required", arg),
+			Error::InvalidValue { arg, message } => {
+				write!(f, "Invalid value for {}: {}", arg, message)
+			}
+		}
+	}
+}
+
+impl crate::error::Error for Error {}
+
+/// A type that an argument can be parsed into
+pub trait FromArg: Sized {
+	/// Parses `arg`, returning an error message if it is invalid.
+	fn from_arg(arg: &OsStr) -> Result<Self, String>;
+}
+
+impl FromArg for OsString {
+	fn from_arg(arg: &OsStr) -> Result<Self, String> {
+		Ok(arg.to_os_string())
+	}
+}
+
+impl FromArg for PathBuf {
+	fn from_arg(arg: &OsStr) -> Result<Self, String> {
+		Ok(arg.into())
+	}
+}
+
+impl FromArg for String {
+	fn from_arg(arg: &OsStr) -> Result<Self, String> {
+		arg.to_str()
+			.map(Into::into)
+			.ok_or_else(|| "not valid unicode".into())
+	}
+}
+
+fn parse_str<T: FromStr>(arg: &OsStr) -> Result<T, String>
+where
+	T::Err: fmt::Display,
+{
+	let arg = arg.to_str().ok_or("not valid unicode")?;
+	arg.parse().map_err(|err: T::Err| err.to_string())
+}
+
+macro_rules! from_str_impls {
+	($($ty:ty)*) => {
+		$(
+			impl FromArg for $ty {
+				fn from_arg(arg: &OsStr) -> Result<Self, String> {
+					parse_str(arg)
+				}
+			}
+		)*
+	};
+}
+
+from_str_impls!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize f32 f64 bool char);
+
+/// Parses the value of the argument named `name` for error messages, such as
+/// `<file>`.
+pub fn parse_arg<T: FromArg>(name: &str, value: &OsStr) -> Result<T, Error> {
+	T::from_arg(value).map_err(|message| Error::InvalidValue {
+		arg: name.into(),
+		message,
+	})
+}
+
+/// An argument returned by [`Parser::next`]
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub enum Arg {
+	/// A short option, such as `-v`
+	Short(char),
+	/// A long option, such as `--verbose`, without the dashes
+	Long(String),
+	/// An argument that isn't an option
+	Value(OsString),
+}
+
+impl Arg {
+	/// Returns `true` if this is the short option `short` or the long option
+	/// `long`.
+	pub fn is(&self, short: Option<char>, long: Option<&str>) -> bool {
+		match self {
+			Arg::Short(c) => Some(*c) == short,
+			Arg::Long(name) => Some(name.as_str()) == long,
+			Arg::Value(_) => false,
+		}
+	}
+
+	/// Returns the error for an argument that the program doesn't expect.
+	pub fn unexpected(&self) -> Error {
+		Error::Unexpected(match self {
+			Arg::Short(c) => format!("-{}", c),
+			Arg::Long(name) => format!("--{}", name),
+			Arg::Value(value) => format!("{:?}", value),
+		})
+	}
+}
+
+/// Splits arguments into options and values. Short options may be grouped, as
+/// in `-abc`, and values may be given as `-n5`, `-n 5`, `--number=5` or
+/// `--number 5`.
+///
+/// ```
+/// use ndless::args::{Arg, Error, Parser};
+/// use ndless::path::PathBuf;
+/// use ndless::prelude::*;
+///
+/// # fn main() -> Result<(), Error> {
+/// let (mut verbose, mut number, mut paths) = (false, 0, vec![]);
+/// let mut parser = Parser::from_env();
+/// while let Some(arg) = parser.next()? {
+/// 	if arg.is(Some('v'), Some("verbose")) {
+/// 		verbose = true;
+/// 	} else if arg.is(Some('n'), Some("number")) {
+/// 		number = parser.parse_value::<u32>()?;
+/// 	} else if let Arg::Value(path) = arg {
+/// 		paths.push(PathBuf::from(path));
+/// 	} else {
+/// 		return Err(arg.unexpected());
+/// 	}
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Parser {
+	args: IntoIter<OsString>,
+	/// A group of short options, and the position of the next one
+	shorts: Option<(String, usize)>,
+	/// The value of the last long option, given with `=`
+	long_value: Option<OsString>,
+	/// The last option, for error messages
+	option: String,
+	/// Whether `--` was found
+	only_values: bool,
+	document: Option<PathBuf>,
+}
+
+impl Parser {
+	/// Creates a parser for `args`, which don't include the path of the
+	/// program.
+	pub fn new(args: impl IntoIterator<Item = OsString>) -> Self {
+		Parser {
+			args: args.into_iter().collect::<Vec<_>>().into_iter(),
+			shorts: None,
+			long_value: None,
+			option: String::new(),
+			only_values: false,
+			document: None,
+		}
+	}
+
+	/// Creates a parser for the arguments of the program, from
+	/// [`env::args_os`].
+	pub fn from_env() -> Self {
+		let mut parser = Parser::new(env::args_os().skip(1));
+		parser.document = env::opened_document();
+		parser
+	}
+
+	/// Returns the path of the document that the program was launched with,
+	/// and skips it, if it was launched by opening a document. See
+	/// [`invocation`]. This must be called before [`next`][Parser::next].
+	pub fn take_document(&mut self) -> Option<PathBuf> {
+		let document = self.document.take()?;
+		self.args.next();
+		Some(document)
+	}
+
+	/// Returns the next argument, or `None` if there are no more.
+	// Not an `Iterator`, as errors and `value` need to borrow the parser
+	#[allow(clippy::should_implement_trait)]
+	pub fn next(&mut self) -> Result<Option<Arg>, Error> {
+		if self.long_value.take().is_some() {
+			return Err(Error::UnexpectedValue(self.option.clone()));
+		}
+		if let Some((shorts, position)) = self.shorts.take() {
+			if let Some(c) = shorts[position..].chars().next() {
+				let next = position + c.len_utf8();
+				if next < shorts.len() {
+					self.shorts = Some((shorts, next));
+				}
+				self.option = format!("-{}", c);
+				return Ok(Some(Arg::Short(c)));
+			}
+		}
+		let arg = match self.args.next() {
+			Some(arg) => arg,
+			None => return Ok(None),
+		};
+		let bytes = arg.as_bytes();
+		if self.only_values || bytes == b"-" || !bytes.starts_with(b"-") {
+			Ok(Some(Arg::Value(arg)))
+		} else if bytes == b"--" {
+			self.only_values = true;
+			self.next()
+		} else if let Some(option) = bytes.strip_prefix(b"--") {
+			let (name, value) = match option.iter().position(|&byte| byte == b'=') {
+				Some(equals) => (&option[..equals], Some(&option[equals + 1..])),
+				None => (option, None),
+			};
+			let name = String::from_utf8_lossy(name).into_owned();
+			self.long_value = value.map(|value| OsStr::from_bytes(value).to_os_string());
+			self.option = format!("--{}", name);
+			Ok(Some(Arg::Long(name)))
+		} else {
+			let shorts = String::from_utf8_lossy(bytes).into_owned();
+			self.shorts = Some((shorts, 1));
+			self.next()
+		}
+	}
+
+	/// Returns the value of the last option returned by
+	/// [`next`][Parser::next]. The value is either part of the same argument,
+	/// as in `-n5` or `--number=5`, or the next argument.
+	pub fn value(&mut self) -> Result<OsString, Error> {
+		if let Some(value) = self.long_value.take() {
+			return Ok(value);
+		}
+		if let Some((shorts, position)) = self.shorts.take() {
+			let value = &shorts[position..];
+			return Ok(value.strip_prefix('=').unwrap_or(value).into());
+		}
+		self.args
+			.next()
+			.ok_or_else(|| Error::MissingValue(self.option.clone()))
+	}
+
+	/// Parses the value of the last option, like [`value`][Parser::value].
+	pub fn parse_value<T: FromArg>(&mut self) -> Result<T, Error> {
+		let value = self.value()?;
+		parse_arg(&self.option, &value)
+	}
+}
+
+/// A type that the arguments of the program can be parsed into. See the
+/// [module-level documentation][self] to derive it.
+pub trait FromArgs: Sized {
+	/// Parses all the arguments of `parser`.
+	fn from_parser(parser: &mut Parser) -> Result<Self, Error>;
+
+	/// Parses `args`, which don't include the path of the program.
+	fn from_args(args: impl IntoIterator<Item = OsString>) -> Result<Self, Error> {
+		Self::from_parser(&mut Parser::new(args))
+	}
+}
+
+/// Parses the arguments of the program.
+pub fn try_parse<T: FromArgs>() -> Result<T, Error> {
+	T::from_parser(&mut Parser::from_env())
+}
+
+/// Parses the arguments of the program, or shows the error in a message box
+/// and exits.
+pub fn parse<T: FromArgs>() -> T {
+	match try_parse() {
+		Ok(args) => args,
+		Err(err) => {
+			crate::msg::msg("Invalid arguments", &err.to_string());
+			crate::process::exit(1)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parser_for(args: &[&str]) -> Parser {
+		Parser::new(args.iter().map(OsString::from))
+	}
+
+	fn value(value: &str) -> Option<Arg> {
+		Some(Arg::Value(value.into()))
+	}
+
+	#[test]
+	fn flags() {
+		let mut parser = parser_for(&["-ab", "--verbose", "-", "file"]);
+		assert_eq!(parser.next(), Ok(Some(Arg::Short('a'))));
+		assert_eq!(parser.next(), Ok(Some(Arg::Short('b'))));
+		assert_eq!(parser.next(), Ok(Some(Arg::Long("verbose".into()))));
+		assert_eq!(parser.next(), Ok(value("-")));
+		assert_eq!(parser.next(), Ok(value("file")));
+		assert_eq!(parser.next(), Ok(None));
+	}
+
+	#[test]
+	fn values() {
+		let mut parser = parser_for(&["-n5", "-n", "6", "--number=7", "--number", "8", "-xn=9"]);
+		for expected in 5..=8 {
+			assert!(parser.next().unwrap().is_some());
+			assert_eq!(parser.parse_value::<u32>(), Ok(expected));
+		}
+		assert_eq!(parser.next(), Ok(Some(Arg::Short('x'))));
+		assert_eq!(parser.next(), Ok(Some(Arg::Short('n'))));
+		assert_eq!(parser.value(), Ok("9".into()));
+		assert_eq!(parser.next(), Ok(None));
+	}
+
+	#[test]
+	fn double_dash() {
+		let mut parser = parser_for(&["--", "-v", "--", "--verbose"]);
+		assert_eq!(parser.next(), Ok(value("-v")));
+		assert_eq!(parser.next(), Ok(value("--")));
+		assert_eq!(parser.next(), Ok(value("--verbose")));
+		assert_eq!(parser.next(), Ok(None));
+	}
+
+	#[test]
+	fn errors() {
+		let mut parser = parser_for(&["--verbose=yes"]);
+		assert!(parser.next().unwrap().is_some());
+		assert_eq!(
+			parser.next(),
+			Err(Error::UnexpectedValue("--verbose".into()))
+		);
+
+		let mut parser = parser_for(&["--number"]);
+		assert!(parser.next().unwrap().is_some());
+		assert_eq!(parser.value(), Err(Error::MissingValue("--number".into())));
+
+		let mut parser = parser_for(&["-n", "five"]);
+		assert!(parser.next().unwrap().is_some());
+		assert!(matches!(
+			parser.parse_value::<u32>(),
+			Err(Error::InvalidValue { .. })
+		));
+	}
+
+	#[derive(FromArgs, PartialEq, Debug)]
+	struct Options {
+		#[arg(short, long)]
+		verbose: bool,
+		#[arg(long, default = 90)]
+		speed: u32,
+		#[arg(short = 'D')]
+		defines: Vec<String>,
+		input: PathBuf,
+		output: Option<PathBuf>,
+		rest: Vec<String>,
+	}
+
+	fn options(args: &[&str]) -> Result<Options, Error> {
+		Options::from_args(args.iter().map(OsString::from))
+	}
+
+	#[test]
+	fn derive() {
+		assert_eq!(
+			options(&["in"]),
+			Ok(Options {
+				verbose: false,
+				speed: 90,
+				defines: vec![],
+				input: "in".into(),
+				output: None,
+				rest: vec![],
+			})
+		);
+		let args = [
+			"-v",
+			"in",
+			"--speed=150",
+			"-DA",
+			"-D",
+			"B",
+			"out",
+			"--",
+			"-x",
+			"y",
+		];
+		assert_eq!(
+			options(&args),
+			Ok(Options {
+				verbose: true,
+				speed: 150,
+				defines: vec!["A".into(), "B".into()],
+				input: "in".into(),
+				output: Some("out".into()),
+				rest: vec!["-x".into(), "y".into()],
+			})
+		);
+	}
+
+	#[test]
+	fn derive_errors() {
+		assert_eq!(options(&[]), Err(Error::Missing("<input>".into())));
+		assert_eq!(
+			options(&["in", "--unknown"]),
+			Err(Error::Unexpected("--unknown".into()))
+		);
+		assert_eq!(
+			options(&["in", "--speed"]),
+			Err(Error::MissingValue("--speed".into()))
+		);
+		assert_eq!(
+			options(&["in", "--verbose=yes"]),
+			Err(Error::UnexpectedValue("--verbose".into()))
+		);
+
+		#[derive(FromArgs, Debug)]
+		struct Positional {
+			_first: String,
+			_second: Option<String>,
+		}
+		let args = ["a", "b", "c"].iter().map(OsString::from);
+		assert!(matches!(
+			Positional::from_args(args),
+			Err(Error::Unexpected(_))
+		));
+	}
+}
@@ -0,0 +1,109 @@
+//! # Ndless configuration
+//! This module contains functions to read and modify the Ndless configuration,
+//! stored in `/documents/ndless/ndless.cfg.tns`.
+//!
+//! The most common use is to associate a file extension with a program, so
+//! that opening a file with that extension in the OS document browser launches
+//! the program. The opened file is then available with
+//! [`env::opened_document`][crate::env::opened_document].
+
+use cstr_core::CStr;
+
+use crate::cstr;
+use crate::io;
+use crate::prelude::*;
+use crate::syscall::{is_available, require, Syscall};
+
+/// Returns the value stored for `key` in the Ndless configuration. Always
+/// `None` on versions of Ndless without a configuration.
+pub fn get(key: &str) -> Option<String> {
+	if !is_available(Syscall::RegisterFileExt) {
+		return None;
+	}
+	let key = cstr!(key);
+	unsafe {
+		ndless_sys::cfg_open();
+		let value = ndless_sys::cfg_get(key.as_ptr());
+		let value = if value.is_null() {
+			None
+		} else {
+			Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+		};
+		ndless_sys::cfg_close();
+		value
+	}
+}
+
+/// Returns the name of the program associated with the file extension `ext`,
+/// if any.
+///
+/// ```
+/// use ndless::config;
+///
+/// if config::extension_program("pdf").is_none() {
+/// 	ndless::msg::msg("PDF", "No PDF viewer is installed");
+/// }
+/// ```
+pub fn extension_program(ext: &str) -> Option<String> {
+	get(&format!("ext.{}", ext))
+}
+
+/// Associates the file extension `ext` with the program named `program`, so
+/// that opening a document called `file.ext.tns` launches `program` with the
+/// document's path as its first argument.
+///
+/// `ext` must not contain the leading `.`, and `program` is the name of the
+/// program without the `.tns` extension (which is removed if present). The
+/// association is saved in the Ndless configuration, so it only has to be
+/// done once, although doing it every time the program runs is harmless.
+///
+/// # Errors
+///
+/// Returns an [`Err`] if:
+///
+/// * The installed version of Ndless doesn't support file associations.
+/// * `ext` or `program` is empty or contains characters that can't be stored in
+///   the configuration.
+/// * `ext` is already associated with a different program. The existing
+///   association is kept, as Ndless doesn't allow overwriting it.
+///
+/// # Examples
+///
+/// ```
+/// use ndless::config;
+///
+/// config::register_extension("md", "markdown").expect("couldn't register .md");
+/// ```
+pub fn register_extension(ext: &str, program: &str) -> io::Result<()> {
+	require(Syscall::RegisterFileExt)?;
+	let program = program.strip_suffix(".tns").unwrap_or(program);
+	if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"file extensions may only contain letters and digits",
+		));
+	}
+	if program.is_empty()
+		|| program
+			.chars()
+			.any(|c| c.is_control() || c == '/' || c == '=')
+	{
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"invalid program name",
+		));
+	}
+	match extension_program(ext) {
+		Some(existing) if existing == program => Ok(()),
+		Some(_) => Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			"the extension is already associated with another program",
+		)),
+		None => {
+			let ext = cstr!(ext);
+			let program = cstr!(program);
+			unsafe { ndless_sys::cfg_register_fileext(ext.as_ptr(), program.as_ptr()) };
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,172 @@
+//! # Crash reports
+//! This module configures what happens when the program panics, with the panic
+//! handler of `ndless-handler`:
+//!
+//! 1. If enabled with [`set_log_file`], a report is appended to a file, with
+//!    the panic message and location, the program's arguments, the time, and
+//!    the Ndless and OS versions.
+//! 2. The hooks registered with [`process::at_exit`][crate::process::at_exit]
+//!    are called, and the state of the calculator is restored: the screen mode,
+//!    interrupts, timers, CPU speed, and so on.
+//! 3. The crash screen is shown, which is a message box unless replaced with
+//!    [`set_screen`].
+//! 4. The program is aborted.
+//!
+//! If the program panics again during these steps, it is aborted immediately.
+//! Panics in the hooks of resident programs only write the report and disable
+//! the hook: see [`resident`][crate::resident].
+//!
+//! With the `unwind` feature, the stack is first unwound to `main`, running
+//! destructors, and panics may be caught: see [`panic`][crate::panic].
+//!
+//! ```
+//! use ndless::crash;
+//! use ndless::env;
+//!
+//! crash::set_log_file(Some(env::get_documents_dir()?.join("crash.txt.tns")));
+//! crash::set_screen(|info| ndless::msg::msg("My program crashed", &info.to_string()));
+//! ```
+
+use core::fmt;
+use core::panic::Location;
+use core::ptr::addr_of_mut;
+
+use crate::fs::OpenOptions;
+use crate::hw::rtc::{self, DateTime};
+use crate::io::{self, Write};
+use crate::path::PathBuf;
+use crate::prelude::*;
+use crate::{env, os};
+
+type Screen = Box<dyn Fn(&CrashInfo)>;
+
+static mut LOG_FILE: Option<PathBuf> = None;
+static mut SCREEN: Option<Screen> = None;
+/// Whether a panic is being handled, to abort on nested panics
+static mut PANICKING: bool = false;
+
+/// Where a panic happened
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct CrashLocation {
+	pub file: String,
+	pub line: u32,
+	pub column: u32,
+}
+
+impl From<&Location<'_>> for CrashLocation {
+	fn from(location: &Location<'_>) -> Self {
+		CrashLocation {
+			file: location.file().into(),
+			line: location.line(),
+			column: location.column(),
+		}
+	}
+}
+
+/// Information about a panic, passed to the crash screen
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct CrashInfo {
+	/// The panic message, if any
+	pub message: Option<String>,
+	/// Where the panic happened, if known
+	pub location: Option<CrashLocation>,
+}
+
+impl fmt::Display for CrashInfo {
+	/// Formats the message and location, as shown in the default message box.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.message {
+			Some(message) => write!(f, "An error occured: {}", message)?,
+			None => write!(f, "An error occured!")?,
+		}
+		if let Some(location) = &self.location {
+			write!(
+				f,
+				"\nIn file {} at line {} column {}",
+				location.file, location.line, location.column
+			)?;
+		}
+		Ok(())
+	}
+}
+
+/// Enables appending crash reports to the file at `path`, or disables it with
+/// `None`, which is the default.
+pub fn set_log_file(path: Option<PathBuf>) {
+	unsafe { LOG_FILE = path };
+}
+
+/// Replaces the message box shown when the program panics with `screen`.
+///
+/// It is called after the state of the calculator has been restored, with
+/// interrupts enabled, so it may use the screen and wait for keys. The program
+/// is aborted when it returns.
+pub fn set_screen(screen: impl Fn(&CrashInfo) + 'static) {
+	unsafe { SCREEN = Some(Box::new(screen)) };
+}
+
+fn write_report(path: &PathBuf, info: &CrashInfo) -> io::Result<()> {
+	let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+	writeln!(file, "Crash at {}", DateTime::from(rtc::now()))?;
+	writeln!(file, "{}", info)?;
+	write!(file, "Arguments:")?;
+	for arg in env::args() {
+		write!(file, " {:?}", arg)?;
+	}
+	writeln!(file)?;
+	writeln!(file, "Ndless revision: {}", os::ndless_revision())?;
+	match os::version() {
+		Some(version) => writeln!(file, "OS: {}", version)?,
+		None => writeln!(file, "OS: unknown")?,
+	}
+	writeln!(file)
+}
+
+/// Writes the crash report, if enabled with [`set_log_file`].
+pub(crate) fn log(info: &CrashInfo) {
+	if let Some(path) = unsafe { &*addr_of_mut!(LOG_FILE) } {
+		let _ = write_report(path, info);
+	}
+}
+
+/// Handles a panic as described in the [module-level documentation][self].
+/// Called by the panic handler of `ndless-handler`.
+#[doc(hidden)]
+pub fn __panic(info: CrashInfo) -> ! {
+	if crate::resident::in_handler() {
+		crate::resident::handler_panicked(&info);
+	}
+	unsafe {
+		let panicking = PANICKING;
+		if panicking {
+			ndless_sys::abort();
+		}
+		PANICKING = true;
+	}
+	#[cfg(feature = "unwind")]
+	let info = match crate::panic::raise(Box::new(info)).downcast() {
+		Ok(info) => *info,
+		Err(_) => unsafe { ndless_sys::abort() },
+	};
+	crashed(&info)
+}
+
+/// Writes the crash report, restores the state of the calculator, and shows
+/// the crash screen, after unwinding if enabled.
+pub(crate) fn crashed(info: &CrashInfo) -> ! {
+	unsafe { PANICKING = true };
+	log(info);
+	crate::__cleanup();
+	match unsafe { &*addr_of_mut!(SCREEN) } {
+		Some(screen) => screen(info),
+		None => crate::msg::msg("Error", &info.to_string()),
+	}
+	crate::release_heap();
+	unsafe { ndless_sys::abort() }
+}
+
+/// Called when [`catch_unwind`][crate::panic::catch_unwind] catches a panic
+#[cfg(feature = "unwind")]
+pub(crate) fn caught() {
+	unsafe { PANICKING = false };
+}
@@ -7,14 +7,17 @@
 use crate::alloc::string::ToString;
 use cstr_core::CStr;
 
+use crate::ffi::{OsStr, OsStrExt, OsString};
 use crate::io;
 use crate::io::ErrorKind;
 use crate::libc;
 use crate::path::Path;
 use crate::path::PathBuf;
 use crate::prelude::*;
+use crate::syscall::{require, Syscall};
 
 pub type Args = IntoIter<String>;
+pub type ArgsOs = IntoIter<OsString>;
 
 /// Returns the arguments which this program was started with.
 ///
@@ -57,6 +60,23 @@ pub fn args() -> Args {
 	}
 }
 
+/// Returns the arguments which this program was started with, like [`args`],
+/// but without panicking if an argument is not valid unicode.
+///
+/// See [`args`](crate::args) to parse them.
+pub fn args_os() -> ArgsOs {
+	unsafe { crate::ARGUMENTS }
+		.map(|args| {
+			args.iter()
+				.map(|arg| {
+					OsStr::from_bytes(unsafe { CStr::from_ptr(*arg) }.to_bytes()).to_os_string()
+				})
+				.collect::<Vec<_>>()
+		})
+		.unwrap_or_default()
+		.into_iter()
+}
+
 /// Returns the current working directory as a [`PathBuf`].
 ///
 /// # Errors
@@ -105,7 +125,46 @@ pub fn set_current_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
 	crate::file_io::sys::os::chdir(path.as_ref())
 }
 
+/// Changes the current working directory to the directory containing the
+/// program, so that files next to it may be opened with relative paths.
+///
+/// # Errors
+///
+/// Returns an [`Err`] if the installed version of Ndless doesn't support this,
+/// if the path of the program isn't known, which is the case for resident
+/// programs, or if the directory couldn't be changed.
+///
+/// # Examples
+///
+/// ```
+/// use ndless::env;
+/// use ndless::fs::File;
+///
+/// env::enable_relative_paths()?;
+/// let levels = File::open("levels.dat.tns")?;
+/// ```
+pub fn enable_relative_paths() -> io::Result<()> {
+	require(Syscall::EnableRelativePaths)?;
+	let args = unsafe { crate::ARGUMENTS }
+		.filter(|args| !args.is_empty())
+		.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "the program path isn't known"))?;
+	match unsafe { ndless_sys::enable_relative_paths(args.as_ptr() as *mut *mut cty::c_char) } {
+		0 => Ok(()),
+		_ => Err(io::Error::new(
+			ErrorKind::Other,
+			"couldn't change to the directory of the program",
+		)),
+	}
+}
+
+/// Returns the path of the directory shown in the OS document browser.
+///
+/// # Errors
+///
+/// Returns an [`Err`] if the installed version of Ndless doesn't support this,
+/// or if the directory couldn't be found.
 pub fn get_documents_dir() -> io::Result<PathBuf> {
+	require(Syscall::GetDocumentsDir)?;
 	unsafe {
 		let ptr = libc::get_documents_dir();
 		if ptr.is_null() {
@@ -115,3 +174,42 @@ pub fn get_documents_dir() -> io::Result<PathBuf> {
 		}
 	}
 }
+
+/// Returns the path of the document that was opened, if the program was
+/// launched by opening a file in the OS document browser.
+///
+/// This is the case when the first argument is the path of an existing file
+/// whose extension is associated with this program. See
+/// [`config::register_extension`][crate::config::register_extension] to set up
+/// an association.
+///
+/// # Examples
+///
+/// ```
+/// use ndless::env;
+///
+/// match env::opened_document() {
+/// 	Some(path) => println!("Opening {}", path.display()),
+/// 	None => println!("Started without a document"),
+/// }
+/// ```
+pub fn opened_document() -> Option<PathBuf> {
+	let (program, document) = unsafe {
+		let args = crate::ARGUMENTS?;
+		(
+			PathBuf::from(OsStr::from_bytes(CStr::from_ptr(*args.first()?).to_bytes())),
+			PathBuf::from(OsStr::from_bytes(CStr::from_ptr(*args.get(1)?).to_bytes())),
+		)
+	};
+	if !document.is_absolute() || !document.is_file() {
+		return None;
+	}
+	// Documents are named `file.ext.tns`
+	let ext = Path::new(document.file_stem()?).extension()?.to_str()?;
+	let program = program.file_stem()?.to_str()?;
+	if crate::config::extension_program(ext)? == program {
+		Some(document)
+	} else {
+		None
+	}
+}
@@ -1,6 +1,14 @@
 //! # Tools to interact with the hardware
 //! This module contains functions to gather information about the calculator.
 
+use crate::syscall::{require, Syscall, Unsupported};
+
+pub mod cpu;
+pub mod power;
+pub mod regs;
+pub mod rtc;
+pub mod screen;
+
 /// Returned by [`hw_type`]
 #[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
 pub enum Type {
@@ -17,8 +25,47 @@ pub fn hw_type() -> Type {
 	}
 }
 
+/// Returned by [`model`]
+#[non_exhaustive]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum Model {
+	/// Classic TI-Nspire with a grayscale screen, either Clickpad or Touchpad
+	Classic,
+	/// TI-Nspire CM/CM-C
+	Cm,
+	/// TI-Nspire CX
+	Cx,
+	/// A model that [`hw_type`] reports as [`Type::Future`]. Ndless only
+	/// documents the values of `hwtype()` for the classic TI-Nspire and the
+	/// CX, so newer models such as the CX II can't be told apart.
+	Other,
+}
+
+/// Returns the calculator model, combining [`hw_type`] and [`is_cm`].
+pub fn model() -> Model {
+	match hw_type() {
+		Type::Nspire => Model::Classic,
+		Type::NspireCX if is_cm() => Model::Cm,
+		Type::NspireCX => Model::Cx,
+		Type::Future(_) => Model::Other,
+	}
+}
+
+/// Returns the hardware subtype, which is 1 on TI-Nspire CM/CM-C.
+///
+/// # Panics
+///
+/// Panics if the installed version of Ndless doesn't support this. Use
+/// [`try_hw_subtype`] to handle this case.
 pub fn hw_subtype() -> u32 {
-	unsafe { ndless_sys::nl_hwsubtype() }
+	try_hw_subtype().unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Returns the hardware subtype like [`hw_subtype`], or an error if the
+/// installed version of Ndless doesn't support it.
+pub fn try_hw_subtype() -> Result<u32, Unsupported> {
+	require(Syscall::HwSubtype)?;
+	Ok(unsafe { ndless_sys::nl_hwsubtype() })
 }
 
 /// since Ndless v3.1. TRUE on classic TI-Nspire.
@@ -30,7 +77,7 @@ pub fn is_classic() -> bool {
 
 /// since Ndless v3.1 r863. TRUE on TI-Nspire CM/CM-C.
 pub fn is_cm() -> bool {
-	hw_subtype() == 1
+	try_hw_subtype() == Ok(1)
 }
 
 /// since Ndless v3.1. TRUE if the device has a screen in color.
@@ -49,39 +96,6 @@ pub fn clear_cache() {
 	unsafe { ndless_sys::clear_cache() }
 }
 
-pub mod screen {
-	/// Returned by [`lcd_type`]
-	#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
-	pub enum Screen {
-		/// 4bit grayscale. Native on classic calcs.
-		Screen320x240x4,
-		/// 8bit paletted mode.
-		Screen320x240x8,
-		/// RGB444
-		Screen320x240x16,
-		/// RGB565. Native on CX before HW-W
-		Screen320x240x565,
-		/// RGB565. Native on CX HW-W
-		Screen240x320x565,
-		Screen320x240x555,
-		Screen240x320x555,
-		Unknown,
-	}
-
-	pub fn lcd_type() -> Screen {
-		match unsafe { ndless_sys::lcd_type() } {
-			ndless_sys::scr_type_t_SCR_320x240_4 => Screen::Screen320x240x4,
-			ndless_sys::scr_type_t_SCR_320x240_8 => Screen::Screen320x240x8,
-			ndless_sys::scr_type_t_SCR_320x240_16 => Screen::Screen320x240x16,
-			ndless_sys::scr_type_t_SCR_320x240_565 => Screen::Screen320x240x565,
-			ndless_sys::scr_type_t_SCR_240x320_565 => Screen::Screen240x320x565,
-			ndless_sys::scr_type_t_SCR_320x240_555 => Screen::Screen320x240x555,
-			ndless_sys::scr_type_t_SCR_240x320_555 => Screen::Screen240x320x555,
-			_ => Screen::Unknown,
-		}
-	}
-}
-
 /// Go to sleep until an interrupt occurs
 pub fn idle() {
 	unsafe { ndless_sys::idle() }
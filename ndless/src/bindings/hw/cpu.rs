@@ -0,0 +1,137 @@
+//! # CPU clock
+//! This module contains functions to change the speed of the processor. Games
+//! may want to run at full speed, while long-running background work can save
+//! batteries by running slower.
+//!
+//! The [timers][crate::timer] run from a separate 32768 Hz clock, so
+//! [`get_ticks`][crate::timer::get_ticks] and
+//! [`thread::sleep`][crate::thread::sleep] are not affected by the CPU speed.
+//! However, anything that waits by counting loop iterations will run faster or
+//! slower, and so does the [serial port][crate::serial] of classic models,
+//! whose baud rate is only correct at the default speed. The speed that the
+//! program started with is restored when it exits, even after a panic.
+
+use crate::hw::regs;
+use crate::timer;
+
+/// Speed that the program was started with, saved the first time the speed is
+/// changed.
+static mut ORIGINAL_SPEED: Option<CpuSpeed> = None;
+
+/// A CPU clock speed. Returned by [`speed`] and [`set_speed`].
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum CpuSpeed {
+	/// 150 MHz, the fastest speed
+	Mhz150,
+	/// 120 MHz
+	Mhz120,
+	/// 90 MHz, the default speed of the OS
+	Mhz90,
+	/// A raw clock configuration not covered by the presets, as written to the
+	/// power management unit
+	Other(u32),
+}
+
+impl CpuSpeed {
+	fn from_raw(raw: u32) -> Self {
+		match raw {
+			0x0000_0002 => CpuSpeed::Mhz150,
+			0x000A_1002 => CpuSpeed::Mhz120,
+			0x0014_1002 => CpuSpeed::Mhz90,
+			raw => CpuSpeed::Other(raw),
+		}
+	}
+
+	fn raw(self) -> u32 {
+		match self {
+			CpuSpeed::Mhz150 => 0x0000_0002,
+			CpuSpeed::Mhz120 => 0x000A_1002,
+			CpuSpeed::Mhz90 => 0x0014_1002,
+			CpuSpeed::Other(raw) => raw,
+		}
+	}
+
+	/// Returns the frequency in MHz, or `None` for unknown configurations.
+	pub fn mhz(self) -> Option<u32> {
+		match self {
+			CpuSpeed::Mhz150 => Some(150),
+			CpuSpeed::Mhz120 => Some(120),
+			CpuSpeed::Mhz90 => Some(90),
+			CpuSpeed::Other(_) => None,
+		}
+	}
+}
+
+/// Returns the current CPU speed.
+///
+/// # Panics
+///
+/// Panics on models whose power management unit isn't known, such as the CX
+/// II, see [`regs::is_supported`].
+pub fn speed() -> CpuSpeed {
+	let power = regs::power().expect(regs::UNSUPPORTED);
+	CpuSpeed::from_raw(power.clock_speed().read())
+}
+
+/// Changes the CPU speed, returning the previous one.
+///
+/// Prefer [`CpuSpeedGuard`] to restore the previous speed automatically.
+pub fn set_speed(speed: CpuSpeed) -> CpuSpeed {
+	let previous = unsafe {
+		let previous = CpuSpeed::from_raw(ndless_sys::set_cpu_speed(speed.raw()));
+		let original = ORIGINAL_SPEED;
+		if original.is_none() {
+			ORIGINAL_SPEED = Some(previous);
+		}
+		previous
+	};
+	timer::resync();
+	previous
+}
+
+/// Changes the CPU speed until dropped, when the previous speed is restored.
+///
+/// ```
+/// use ndless::hw::cpu::{CpuSpeed, CpuSpeedGuard};
+///
+/// {
+/// 	let _fast = CpuSpeedGuard::new(CpuSpeed::Mhz150);
+/// 	// render at full speed
+/// }
+/// // back to the previous speed
+/// ```
+#[must_use = "the previous speed is restored when the guard is dropped"]
+#[derive(Debug)]
+pub struct CpuSpeedGuard {
+	previous: CpuSpeed,
+}
+
+impl CpuSpeedGuard {
+	pub fn new(speed: CpuSpeed) -> Self {
+		CpuSpeedGuard {
+			previous: set_speed(speed),
+		}
+	}
+
+	/// Returns the speed that will be restored.
+	pub fn previous(&self) -> CpuSpeed {
+		self.previous
+	}
+}
+
+impl Drop for CpuSpeedGuard {
+	fn drop(&mut self) {
+		set_speed(self.previous);
+	}
+}
+
+/// Restores the speed that the program was started with
+#[doc(hidden)]
+pub fn __cleanup() {
+	if let Some(speed) = unsafe { ORIGINAL_SPEED } {
+		set_speed(speed);
+		// Cleared afterwards, as `set_speed` records the speed it replaces if
+		// none is saved
+		unsafe { ORIGINAL_SPEED = None };
+	}
+}
@@ -0,0 +1,110 @@
+//! # Battery and power status
+//! This module reads the voltages of the battery and of the USB port, and
+//! where the calculator is getting power from, for long-running programs that
+//! want to warn the user or save their work before the calculator shuts down.
+//!
+//! Values are read from the analog-to-digital converter, which the OS samples
+//! regularly to draw the battery indicator. The battery and USB channels
+//! measure 155 units per volt, as documented on
+//! [Hackspire](https://hackspire.org/index.php/Memory-mapped_I/O_ports#C4000000_-_ADC_.28Analog-to-Digital_Converter.29).
+//! The other values are derived from the voltages: the hardware has no
+//! documented register telling them apart. Which voltage corresponds to an
+//! empty battery depends on the kind of batteries installed, and is left to
+//! the program.
+
+use crate::hw::is_classic;
+use crate::hw::regs;
+
+/// The kind of batteries installed
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum BatteryKind {
+	/// A rechargeable battery pack, which is the only option on the CX
+	Rechargeable,
+	/// 4 AAA batteries, only supported by classic models
+	Aaa,
+}
+
+/// Returned by [`status`]. Fields are `None` when they can't be read on the
+/// model.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct PowerStatus {
+	/// The battery voltage, in millivolts
+	pub battery_mv: Option<u32>,
+	/// The voltage of the USB port, in millivolts
+	pub vbus_mv: Option<u32>,
+	/// Whether the calculator is connected to USB power
+	pub usb_powered: Option<bool>,
+	/// Whether the rechargeable battery is charging: USB power is connected and
+	/// the battery isn't full yet
+	pub charging: Option<bool>,
+	/// The kind of batteries installed, also `None` if no battery was detected
+	/// and the calculator is running on USB power
+	pub battery_kind: Option<BatteryKind>,
+}
+
+/// The ADC channel measuring the battery voltage
+const BATTERY_CHANNEL: usize = 1;
+/// The ADC channel measuring the USB bus voltage
+const VBUS_CHANNEL: usize = 2;
+/// ADC units per volt on the battery and USB channels
+const UNITS_PER_VOLT: u32 = 155;
+
+/// Below this, there is no battery
+const NO_BATTERY_MV: u32 = 2000;
+/// Above this, a classic calculator has AAA batteries, as a rechargeable pack
+/// stops charging at 4.2 V
+const AAA_MIN_MV: u32 = 4500;
+/// Above this, a rechargeable pack is full
+const FULL_MV: u32 = 4150;
+/// Above this, USB power is connected, which is nominally 5 V
+const VBUS_PRESENT_MV: u32 = 4000;
+
+/// Returns the last value converted by `adc` on `channel`, in millivolts
+fn read_mv(adc: regs::Adc, channel: usize) -> u32 {
+	(adc.channel_value(channel).read() & 0x3FF) * 1000 / UNITS_PER_VOLT
+}
+
+/// Returns the current battery and power status.
+///
+/// ```
+/// use ndless::hw::power;
+///
+/// let status = power::status();
+/// if status.usb_powered == Some(false) && status.battery_mv.map_or(false, |mv| mv < 3500) {
+/// 	ndless::msg::msg("Battery low", "Saving your work...");
+/// }
+/// ```
+pub fn status() -> PowerStatus {
+	let adc = match regs::adc() {
+		Some(adc) => adc,
+		// The ADC isn't known
+		None => {
+			return PowerStatus {
+				battery_mv: None,
+				vbus_mv: None,
+				usb_powered: None,
+				charging: None,
+				battery_kind: None,
+			}
+		}
+	};
+	let battery_mv = read_mv(adc, BATTERY_CHANNEL);
+	let vbus_mv = read_mv(adc, VBUS_CHANNEL);
+	let usb_powered = vbus_mv >= VBUS_PRESENT_MV;
+	let battery_kind = if battery_mv < NO_BATTERY_MV {
+		None
+	} else if is_classic() && battery_mv >= AAA_MIN_MV {
+		Some(BatteryKind::Aaa)
+	} else {
+		Some(BatteryKind::Rechargeable)
+	};
+	PowerStatus {
+		battery_mv: Some(battery_mv),
+		vbus_mv: Some(vbus_mv),
+		usb_powered: Some(usb_powered),
+		charging: Some(
+			usb_powered && battery_kind == Some(BatteryKind::Rechargeable) && battery_mv < FULL_MV,
+		),
+		battery_kind,
+	}
+}
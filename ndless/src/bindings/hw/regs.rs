@@ -0,0 +1,662 @@
+//! # Memory-mapped hardware registers
+//! This module contains typed access to the hardware registers of the
+//! calculator. Register blocks are returned by functions such as [`lcd`] that
+//! pick the right base address and layout for the running model, so there is
+//! no need to check for classic or CX hardware manually.
+//!
+//! Writing to these registers changes the hardware directly, bypassing the OS,
+//! so [`Reg::write`] is unsafe. Anything changed must be restored before the
+//! program exits, or the OS may misbehave. Modules such as
+//! [`timer`][crate::timer] and [`serial`][crate::serial] provide safe wrappers
+//! that take care of this.
+//!
+//! Only the classic TI-Nspire and the CX layouts are known. On other models,
+//! such as the CX II, where some peripherals moved, the register blocks are
+//! `None` instead of pointing at the wrong peripheral, and the modules built on
+//! them panic.
+//!
+//! Register descriptions come from
+//! [Hackspire](https://hackspire.org/index.php/Memory-mapped_I/O_ports).
+#![allow(clippy::unreadable_literal)]
+
+use core::marker::PhantomData;
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::hw::{hw_type, is_classic, Type};
+
+/// A memory-mapped register, which is accessed with volatile reads and writes.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Reg<T = u32> {
+	addr: usize,
+	_type: PhantomData<T>,
+}
+
+impl<T> Clone for Reg<T> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+impl<T> Copy for Reg<T> {}
+
+impl<T: Copy> Reg<T> {
+	/// Creates a register at `addr`.
+	///
+	/// # Safety
+	///
+	/// `addr` must be the address of a hardware register of type `T`, or at
+	/// least readable and writable memory.
+	pub const unsafe fn new(addr: usize) -> Self {
+		Reg {
+			addr,
+			_type: PhantomData,
+		}
+	}
+
+	pub fn addr(&self) -> usize {
+		self.addr
+	}
+
+	pub fn read(&self) -> T {
+		unsafe { read_volatile(self.addr as *const T) }
+	}
+
+	/// Writes `value` to the register.
+	///
+	/// # Safety
+	///
+	/// Writing to a register changes the hardware behind the back of the OS,
+	/// and may corrupt memory, e.g. by moving the framebuffer or changing the
+	/// clocks. The caller must make sure the value is valid for the register
+	/// and that whatever is changed is restored before the program exits.
+	pub unsafe fn write(&self, value: T) {
+		write_volatile(self.addr as *mut T, value)
+	}
+
+	/// Reads the register, passes the value to `f`, and writes the result
+	/// back.
+	///
+	/// # Safety
+	///
+	/// See [`write`][Reg::write].
+	pub unsafe fn modify(&self, f: impl FnOnce(T) -> T) {
+		self.write(f(self.read()))
+	}
+}
+
+/// The panic message of the safe wrappers when the registers of the model
+/// aren't known
+pub(crate) const UNSUPPORTED: &str =
+	"the hardware registers of this calculator model are not known";
+
+/// Returns `true` if the register layout of the running model is known: the
+/// classic TI-Nspire, the CM and the CX. Newer models, such as the CX II, are
+/// reported as [`Type::Future`] by [`hw_type`], and aren't supported.
+pub fn is_supported() -> bool {
+	!matches!(hw_type(), Type::Future(_))
+}
+
+/// Selects an address whose mapping depends on the hardware type, like
+/// libndls' `IO()` macro: `classic` on classic TI-Nspire, and `cx` on the CM
+/// and CX.
+///
+/// Unlike `IO()`, this returns `None` on models that aren't
+/// [supported][is_supported], such as the CX II, where some peripherals moved.
+///
+/// ```
+/// use ndless::hw::regs::{io, Reg};
+///
+/// if let Some(addr) = io(0xC000001C, 0xC0000018) {
+/// 	let lcd_control: Reg = unsafe { Reg::new(addr) };
+/// }
+/// ```
+pub fn io(classic: usize, cx: usize) -> Option<usize> {
+	block(layout(classic, cx))
+}
+
+/// Selects `classic` or `cx`, for register blocks that only exist on supported
+/// models
+fn layout(classic: usize, cx: usize) -> usize {
+	if is_classic() {
+		classic
+	} else {
+		cx
+	}
+}
+
+/// Returns `block` if the model is [supported][is_supported]
+fn block<T>(block: T) -> Option<T> {
+	if is_supported() {
+		Some(block)
+	} else {
+		None
+	}
+}
+
+/// Creates the register at `offset` bytes from `base`
+fn reg<T: Copy>(base: usize, offset: usize) -> Reg<T> {
+	unsafe { Reg::new(base + offset) }
+}
+
+/// The hardware timers. Each has a different base address, and the layout
+/// depends on the model: see [`timer`].
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum TimerId {
+	/// The fast timer at `0x90010000`
+	Fast,
+	/// The first timer at `0x900C0000`, used by
+	/// [`get_ticks`][crate::timer::get_ticks]
+	First,
+	/// The second timer at `0x900D0000`, used to wake up from
+	/// [`idle`][crate::hw::idle]
+	Second,
+}
+
+impl TimerId {
+	fn base(self) -> usize {
+		match self {
+			TimerId::Fast => 0x90010000,
+			TimerId::First => 0x900C0000,
+			TimerId::Second => 0x900D0000,
+		}
+	}
+}
+
+/// A hardware timer, whose layout depends on the model
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Timer {
+	Classic(ClassicTimer),
+	Sp804(Sp804Timer),
+}
+
+/// Returns the registers of the timer `id`, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn timer(id: TimerId) -> Option<Timer> {
+	if !is_supported() {
+		None
+	} else if is_classic() {
+		Some(Timer::Classic(ClassicTimer { base: id.base() }))
+	} else {
+		Some(Timer::Sp804(Sp804Timer { base: id.base() }))
+	}
+}
+
+/// A timer of the classic TI-Nspire, counting from a 32768 Hz clock
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ClassicTimer {
+	base: usize,
+}
+
+impl ClassicTimer {
+	/// The current value. Counts up or down depending on `control`.
+	pub fn value(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// The clock is divided by this value plus one
+	pub fn divider(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	pub fn control(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+}
+
+/// An ARM SP804 dual timer, used on the CX and newer. Only the first of each
+/// pair is exposed.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Sp804Timer {
+	base: usize,
+}
+
+impl Sp804Timer {
+	/// The value that the timer restarts from
+	pub fn load(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// The current value, counting down
+	pub fn value(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	pub fn control(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	/// Write any value to clear the interrupt
+	pub fn int_clear(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	pub fn raw_int_status(&self) -> Reg {
+		reg(self.base, 0x10)
+	}
+
+	pub fn masked_int_status(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+
+	/// Like [`load`][Sp804Timer::load], but doesn't restart the current count
+	pub fn background_load(&self) -> Reg {
+		reg(self.base, 0x18)
+	}
+
+	/// Selects the clock of the timer. `0xA` selects the 32768 Hz clock.
+	pub fn clock_source(&self) -> Reg {
+		reg(self.base, 0x80)
+	}
+}
+
+/// The LCD controller, an ARM PL110 on classic models and PL111 otherwise
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Lcd {
+	base: usize,
+}
+
+/// Returns the registers of the LCD controller, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn lcd() -> Option<Lcd> {
+	block(Lcd { base: 0xC0000000 })
+}
+
+impl Lcd {
+	/// One of the four timing registers, `0..=3`
+	pub fn timing(&self, n: usize) -> Reg {
+		assert!(n < 4, "there are only 4 timing registers");
+		reg(self.base, n * 4)
+	}
+
+	/// The address of the framebuffer being displayed
+	pub fn upper_base(&self) -> Reg {
+		reg(self.base, 0x10)
+	}
+
+	pub fn lower_base(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+
+	/// Contains the color mode and whether the LCD is enabled
+	pub fn control(&self) -> Reg {
+		reg(self.base, layout(0x1C, 0x18))
+	}
+
+	pub fn int_mask(&self) -> Reg {
+		reg(self.base, layout(0x18, 0x1C))
+	}
+
+	/// One of the 128 palette registers, each containing two colors
+	pub fn palette(&self, n: usize) -> Reg {
+		assert!(n < 128, "there are only 128 palette registers");
+		reg(self.base, 0x200 + n * 4)
+	}
+}
+
+/// The keypad controller
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Keypad {
+	base: usize,
+}
+
+/// Returns the registers of the keypad controller, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn keypad() -> Option<Keypad> {
+	block(Keypad { base: 0x900E0000 })
+}
+
+impl Keypad {
+	/// Selects the scan mode
+	pub fn control(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// The number of rows and columns to scan
+	pub fn size(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	pub fn int_status(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	pub fn int_mask(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	/// The keys pressed in one of the 8 rows, one bit per key. On classic
+	/// models, bits are cleared for pressed keys instead of set.
+	pub fn row(&self, n: usize) -> Reg<u16> {
+		assert!(n < 8, "there are only 8 keypad rows");
+		reg(self.base, 0x10 + n * 2)
+	}
+}
+
+/// The analog-to-digital converter, used to measure battery and power
+/// voltages
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Adc {
+	base: usize,
+}
+
+/// Returns the registers of the analog-to-digital converter, or `None` if the
+/// model isn't [supported][is_supported].
+pub fn adc() -> Option<Adc> {
+	block(Adc { base: 0xC4000000 })
+}
+
+impl Adc {
+	pub fn int_status(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	pub fn int_mask(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	/// Write the interrupt status bits to acknowledge them
+	pub fn int_ack(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	/// Starts a conversion of channel `n` when written to
+	pub fn channel_command(&self, n: usize) -> Reg {
+		assert!(n < 7, "there are only 7 ADC channels");
+		reg(self.base, 0x100 + n * 0x20)
+	}
+
+	/// The last converted value of channel `n`, in 10 bits
+	pub fn channel_value(&self, n: usize) -> Reg {
+		assert!(n < 7, "there are only 7 ADC channels");
+		reg(self.base, 0x110 + n * 0x20)
+	}
+}
+
+/// The power management unit
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Power {
+	base: usize,
+}
+
+/// Returns the registers of the power management unit, or `None` if the model
+/// isn't [supported][is_supported].
+pub fn power() -> Option<Power> {
+	block(Power { base: 0x900B0000 })
+}
+
+impl Power {
+	/// The CPU and bus clock configuration, see
+	/// [`hw::cpu`][crate::hw::cpu]
+	pub fn clock_speed(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// Write 4 to apply a change of [`clock_speed`][Power::clock_speed]
+	pub fn clock_change(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	/// The events that wake up the calculator from sleep
+	pub fn wake_mask(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+
+	/// Bits set disable the clock of a peripheral
+	pub fn disable(&self) -> Reg {
+		reg(self.base, 0x18)
+	}
+
+	/// Bits set disable the clock of a peripheral, for more peripherals
+	pub fn disable2(&self) -> Reg {
+		reg(self.base, 0x20)
+	}
+}
+
+/// The interrupt controller, an ARM PL190 vectored interrupt controller on the
+/// CX and newer. Each bit of the registers corresponds to an interrupt line.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct InterruptController {
+	base: usize,
+}
+
+/// Returns the registers of the interrupt controller, or `None` if the model
+/// isn't [supported][is_supported].
+pub fn interrupt_controller() -> Option<InterruptController> {
+	block(InterruptController { base: 0xDC000000 })
+}
+
+impl InterruptController {
+	/// The interrupts that are both enabled and pending
+	pub fn status(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// The interrupts that are pending, whether enabled or not
+	pub fn raw_status(&self) -> Reg {
+		reg(self.base, layout(0x04, 0x08))
+	}
+
+	/// Reads the enabled interrupts. Bits written as 1 enable the interrupt.
+	pub fn enable(&self) -> Reg {
+		reg(self.base, layout(0x08, 0x10))
+	}
+
+	/// Bits written as 1 disable the interrupt
+	pub fn enable_clear(&self) -> Reg {
+		reg(self.base, layout(0x0C, 0x14))
+	}
+}
+
+/// The serial port, whose layout depends on the model
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Uart {
+	Classic(ClassicUart),
+	Pl011(Pl011Uart),
+}
+
+/// Returns the registers of the serial port, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn uart() -> Option<Uart> {
+	if !is_supported() {
+		None
+	} else if is_classic() {
+		Some(Uart::Classic(ClassicUart { base: 0x90020000 }))
+	} else {
+		Some(Uart::Pl011(Pl011Uart { base: 0x90020000 }))
+	}
+}
+
+/// A 16550-compatible UART, used on classic models
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ClassicUart {
+	base: usize,
+}
+
+impl ClassicUart {
+	/// Reads a received byte or writes a byte to transmit. When the divisor
+	/// latch bit of [`line_control`][ClassicUart::line_control] is set, the
+	/// low byte of the baud rate divisor instead.
+	pub fn data(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// Enables interrupts. When the divisor latch bit is set, the high byte of
+	/// the baud rate divisor instead.
+	pub fn int_enable(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	/// Reads the pending interrupt, or writes the FIFO configuration
+	pub fn int_id_fifo_control(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	/// The data format, and bit 7 selects the divisor latch
+	pub fn line_control(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	pub fn modem_control(&self) -> Reg {
+		reg(self.base, 0x10)
+	}
+
+	/// Bit 0 is set when data was received, bits 1 to 4 are error flags, and
+	/// bit 5 is set when a byte can be written
+	pub fn line_status(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+}
+
+/// An ARM PL011 UART, used on the CX and newer
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Pl011Uart {
+	base: usize,
+}
+
+impl Pl011Uart {
+	/// Reads a received byte with its error flags in bits 8 to 11, or writes a
+	/// byte to transmit
+	pub fn data(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// Reads the error flags, or clears them when written
+	pub fn rx_status(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	/// Bit 4 is set when the receive FIFO is empty, and bit 5 when the transmit
+	/// FIFO is full
+	pub fn flags(&self) -> Reg {
+		reg(self.base, 0x18)
+	}
+
+	/// The integer part of the baud rate divisor
+	pub fn int_baud_divisor(&self) -> Reg {
+		reg(self.base, 0x24)
+	}
+
+	/// The fractional part of the baud rate divisor, in 64ths
+	pub fn frac_baud_divisor(&self) -> Reg {
+		reg(self.base, 0x28)
+	}
+
+	/// The data format. Writing it applies the baud rate divisors.
+	pub fn line_control(&self) -> Reg {
+		reg(self.base, 0x2C)
+	}
+
+	/// Enables the UART, transmitting and receiving
+	pub fn control(&self) -> Reg {
+		reg(self.base, 0x30)
+	}
+
+	pub fn int_mask(&self) -> Reg {
+		reg(self.base, 0x38)
+	}
+
+	pub fn raw_int_status(&self) -> Reg {
+		reg(self.base, 0x3C)
+	}
+
+	pub fn masked_int_status(&self) -> Reg {
+		reg(self.base, 0x40)
+	}
+
+	/// Write the interrupt status bits to clear them
+	pub fn int_clear(&self) -> Reg {
+		reg(self.base, 0x44)
+	}
+}
+
+/// The real-time clock, counting seconds since 1997-01-01 00:00
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Rtc {
+	base: usize,
+}
+
+/// Returns the registers of the real-time clock, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn rtc() -> Option<Rtc> {
+	block(Rtc { base: 0x90090000 })
+}
+
+impl Rtc {
+	/// The current time, in seconds
+	pub fn value(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	/// An interrupt is raised when [`value`][Rtc::value] reaches this
+	pub fn alarm(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	/// Write a time to change [`value`][Rtc::value]. The change takes effect
+	/// after about a second.
+	pub fn set(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	/// Bit 0 enables the alarm interrupt
+	pub fn int_mask(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	/// Bit 0 is set when the alarm fired. Write 1 to acknowledge it.
+	pub fn int_status(&self) -> Reg {
+		reg(self.base, 0x10)
+	}
+
+	/// Bits are set while writes to [`set`][Rtc::set] and
+	/// [`alarm`][Rtc::alarm] are pending
+	pub fn status(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+}
+
+/// The watchdog timer, an ARM SP805, which resets the calculator or raises an
+/// interrupt when it counts down to 0
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Watchdog {
+	base: usize,
+}
+
+/// Returns the registers of the watchdog timer, or `None` if the model isn't
+/// [supported][is_supported].
+pub fn watchdog() -> Option<Watchdog> {
+	block(Watchdog { base: 0x90060000 })
+}
+
+impl Watchdog {
+	pub fn load(&self) -> Reg {
+		reg(self.base, 0x00)
+	}
+
+	pub fn value(&self) -> Reg {
+		reg(self.base, 0x04)
+	}
+
+	/// Bit 0 enables the interrupt, bit 1 enables resetting
+	pub fn control(&self) -> Reg {
+		reg(self.base, 0x08)
+	}
+
+	pub fn int_clear(&self) -> Reg {
+		reg(self.base, 0x0C)
+	}
+
+	pub fn raw_int_status(&self) -> Reg {
+		reg(self.base, 0x10)
+	}
+
+	pub fn masked_int_status(&self) -> Reg {
+		reg(self.base, 0x14)
+	}
+
+	/// Write `0x1ACCE551` to unlock the other registers, and anything else to
+	/// lock them
+	pub fn lock(&self) -> Reg {
+		reg(self.base, 0xC00)
+	}
+}
@@ -0,0 +1,328 @@
+//! # Real-time clock
+//! This module contains functions to read and change the calculator's clock,
+//! and to be notified at a given time with an alarm. [`SystemTime::now`] also
+//! reads from this clock, so it may be compared with file modification times.
+//!
+//! The clock only has a resolution of one second, and no time zone conversion
+//! is done: the time is what the user set in the OS. Use [`DateTime`] to
+//! display it.
+//!
+//! On models whose clock isn't known, such as the CX II, the functions of this
+//! module panic: see [`regs::is_supported`].
+
+use core::fmt;
+
+use crate::hw::regs;
+use crate::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds between the Unix epoch and 1997-01-01 00:00, when the hardware
+/// clock starts counting
+const RTC_EPOCH: u32 = 852_076_800;
+
+/// Alarm configuration that the program was started with, saved the first time
+/// the alarm is changed.
+static mut ORIGINAL_ALARM: Option<(u32, u32)> = None;
+
+/// Returns the registers of the clock, panicking if they aren't known
+fn rtc() -> regs::Rtc {
+	regs::rtc().expect(regs::UNSUPPORTED)
+}
+
+/// Returns the value of the hardware clock, in seconds since the Unix epoch.
+pub(crate) fn unix_secs() -> u32 {
+	rtc().value().read().saturating_add(RTC_EPOCH)
+}
+
+fn to_rtc(time: SystemTime) -> u32 {
+	let secs = time
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |duration| duration.as_secs());
+	secs.saturating_sub(RTC_EPOCH.into()).min(u32::MAX.into()) as u32
+}
+
+/// Returns the current time. This is the same as [`SystemTime::now`].
+pub fn now() -> SystemTime {
+	UNIX_EPOCH + Duration::from_secs(unix_secs().into())
+}
+
+/// Changes the time of the calculator's clock. Times before 1997 are clamped to
+/// 1997-01-01 00:00.
+///
+/// The new time takes effect after about a second. It is kept after the
+/// program exits, in the same way as changing it in the OS settings.
+pub fn set(time: SystemTime) {
+	unsafe { rtc().set().write(to_rtc(time)) };
+}
+
+/// Sets the alarm to fire at `time`. Use [`alarm_fired`] to check whether it
+/// fired. Only one alarm may be set at a time, so this replaces any previous
+/// alarm. The previous alarm is restored when the program exits.
+///
+/// ```
+/// use ndless::hw::rtc;
+/// use ndless::time::Duration;
+///
+/// rtc::set_alarm(rtc::now() + Duration::from_secs(60));
+/// while !rtc::alarm_fired() {
+/// 	ndless::hw::idle();
+/// }
+/// ```
+pub fn set_alarm(time: SystemTime) {
+	let rtc = rtc();
+	save_alarm();
+	unsafe {
+		rtc.int_mask().write(0);
+		rtc.int_status().write(1);
+		rtc.alarm().write(to_rtc(time));
+		rtc.int_mask().write(1);
+	}
+}
+
+/// Disables the alarm set with [`set_alarm`].
+pub fn cancel_alarm() {
+	let rtc = rtc();
+	save_alarm();
+	unsafe {
+		rtc.int_mask().write(0);
+		rtc.int_status().write(1);
+	}
+}
+
+/// Returns `true` if the alarm set with [`set_alarm`] fired since the last
+/// call, acknowledging it.
+pub fn alarm_fired() -> bool {
+	let rtc = rtc();
+	let fired = rtc.int_status().read() & 1 != 0;
+	if fired {
+		unsafe { rtc.int_status().write(1) };
+	}
+	fired
+}
+
+fn save_alarm() {
+	unsafe {
+		let original = ORIGINAL_ALARM;
+		if original.is_none() {
+			let rtc = rtc();
+			ORIGINAL_ALARM = Some((rtc.alarm().read(), rtc.int_mask().read()));
+		}
+	}
+}
+
+/// Restores the alarm that the program was started with
+#[doc(hidden)]
+pub fn __cleanup() {
+	if let Some((alarm, int_mask)) = unsafe { ORIGINAL_ALARM } {
+		let rtc = rtc();
+		unsafe {
+			rtc.int_mask().write(0);
+			rtc.int_status().write(1);
+			rtc.alarm().write(alarm);
+			rtc.int_mask().write(int_mask);
+			ORIGINAL_ALARM = None;
+		}
+	}
+}
+
+/// A day of the week
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum Weekday {
+	Monday,
+	Tuesday,
+	Wednesday,
+	Thursday,
+	Friday,
+	Saturday,
+	Sunday,
+}
+
+/// A calendar date and time, for displaying a [`SystemTime`].
+///
+/// ```
+/// use ndless::hw::rtc::{self, DateTime};
+/// use ndless::prelude::*;
+///
+/// let now = DateTime::from(rtc::now());
+/// println!("Today is {}-{:02}-{:02}", now.year, now.month, now.day);
+/// println!("{}", now); // 2020-04-01 12:30:00
+/// ```
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy, Hash)]
+pub struct DateTime {
+	pub year: u16,
+	/// From 1 to 12
+	pub month: u8,
+	/// From 1 to 31
+	pub day: u8,
+	/// From 0 to 23
+	pub hour: u8,
+	/// From 0 to 59
+	pub minute: u8,
+	/// From 0 to 59
+	pub second: u8,
+}
+
+impl DateTime {
+	/// Returns the day of the week.
+	pub fn weekday(&self) -> Weekday {
+		// 1970-01-01 was a Thursday
+		match (days_from_civil(self.year, self.month, self.day) + 3) % 7 {
+			0 => Weekday::Monday,
+			1 => Weekday::Tuesday,
+			2 => Weekday::Wednesday,
+			3 => Weekday::Thursday,
+			4 => Weekday::Friday,
+			5 => Weekday::Saturday,
+			_ => Weekday::Sunday,
+		}
+	}
+
+	/// Converts the date back to a [`SystemTime`], or `None` if it isn't a
+	/// valid date after the Unix epoch.
+	pub fn to_system_time(&self) -> Option<SystemTime> {
+		if self.year < 1970
+			|| !(1..=12).contains(&self.month)
+			|| self.day < 1
+			|| self.day > days_in_month(self.year, self.month)
+			|| self.hour > 23
+			|| self.minute > 59
+			|| self.second > 59
+		{
+			return None;
+		}
+		let secs = days_from_civil(self.year, self.month, self.day) * 86400
+			+ u64::from(self.hour) * 3600
+			+ u64::from(self.minute) * 60
+			+ u64::from(self.second);
+		UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+	}
+}
+
+impl From<SystemTime> for DateTime {
+	/// Converts `time` to a date. Times before the Unix epoch are clamped to
+	/// 1970-01-01 00:00.
+	fn from(time: SystemTime) -> Self {
+		let secs = time
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |duration| duration.as_secs());
+		let (year, month, day) = civil_from_days(secs / 86400);
+		let secs = secs % 86400;
+		DateTime {
+			year,
+			month,
+			day,
+			hour: (secs / 3600) as u8,
+			minute: (secs / 60 % 60) as u8,
+			second: (secs % 60) as u8,
+		}
+	}
+}
+
+impl fmt::Display for DateTime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}-{:02}-{:02} {:02}:{:02}:{:02}",
+			self.year, self.month, self.day, self.hour, self.minute, self.second
+		)
+	}
+}
+
+fn is_leap_year(year: u16) -> bool {
+	year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+	match month {
+		2 if is_leap_year(year) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// Returns the number of days since 1970-01-01, for dates after it. See
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+	let year = u64::from(year) - if month <= 2 { 1 } else { 0 };
+	let era = year / 400;
+	let year_of_era = year % 400;
+	let month = u64::from(month);
+	let day_of_year =
+		(153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + u64::from(day) - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the year, month, and day for a number of days since 1970-01-01. See
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u16, u8, u8) {
+	let days = days + 719_468;
+	let era = days / 146_097;
+	let day_of_era = days % 146_097;
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let mp = (5 * day_of_year + 2) / 153;
+	let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year as u16, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rtc_epoch() {
+		assert_eq!(days_from_civil(1970, 1, 1), 0);
+		assert_eq!(days_from_civil(1997, 1, 1), u64::from(RTC_EPOCH) / 86400);
+		assert_eq!(civil_from_days(u64::from(RTC_EPOCH) / 86400), (1997, 1, 1));
+		assert_eq!(to_rtc(UNIX_EPOCH), 0);
+	}
+
+	#[test]
+	fn leap_days() {
+		assert!(is_leap_year(2000));
+		assert!(is_leap_year(2024));
+		assert!(!is_leap_year(2100));
+		assert_eq!(days_from_civil(2000, 2, 29), 11016);
+		assert_eq!(civil_from_days(11016), (2000, 2, 29));
+		assert_eq!(civil_from_days(11017), (2000, 3, 1));
+		assert_eq!(days_from_civil(2100, 2, 28), 47540);
+		assert_eq!(civil_from_days(47541), (2100, 3, 1));
+	}
+
+	#[test]
+	fn round_trip() {
+		for days in 0..100_000 {
+			let (year, month, day) = civil_from_days(days);
+			assert!((1..=days_in_month(year, month)).contains(&day));
+			assert_eq!(days_from_civil(year, month, day), days);
+		}
+	}
+
+	#[test]
+	fn date_time() {
+		let date = DateTime {
+			year: 1997,
+			month: 1,
+			day: 1,
+			hour: 0,
+			minute: 0,
+			second: 0,
+		};
+		let time = date.to_system_time().unwrap();
+		assert_eq!(to_rtc(time), 0);
+		assert_eq!(DateTime::from(time), date);
+		assert_eq!(date.weekday(), Weekday::Wednesday);
+
+		let leap_day = DateTime {
+			year: 2100,
+			month: 2,
+			day: 29,
+			..date
+		};
+		assert_eq!(leap_day.to_system_time(), None);
+	}
+}
@@ -0,0 +1,222 @@
+//! # Screen
+//! This module switches the mode of the LCD, controls what is on the screen
+//! when the program returns, and saves and restores the LCD, e.g. to show an
+//! overlay over the screen of the OS and put it back exactly as it was:
+//!
+//! ```
+//! use ndless::hw::screen::ScreenSnapshot;
+//!
+//! let snapshot = ScreenSnapshot::capture()?;
+//! // Draw the overlay
+//! drop(snapshot);
+//! ```
+
+use crate::alloc::collections::TryReserveError;
+use crate::hw::regs;
+use crate::prelude::*;
+use crate::syscall::Unsupported;
+
+/// The LCD configuration that the program was started with, saved by
+/// [`__init`]
+static mut ORIGINAL_CONTROL: Option<u32> = None;
+
+/// Returned by [`lcd_type`]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum Screen {
+	/// 4bit grayscale. Native on classic calcs.
+	Screen320x240x4,
+	/// 8bit paletted mode.
+	Screen320x240x8,
+	/// RGB444
+	Screen320x240x16,
+	/// RGB565. Native on CX before HW-W
+	Screen320x240x565,
+	/// RGB565. Native on CX HW-W
+	Screen240x320x565,
+	Screen320x240x555,
+	Screen240x320x555,
+	Unknown,
+}
+
+pub fn lcd_type() -> Screen {
+	match unsafe { ndless_sys::lcd_type() } {
+		ndless_sys::scr_type_t_SCR_320x240_4 => Screen::Screen320x240x4,
+		ndless_sys::scr_type_t_SCR_320x240_8 => Screen::Screen320x240x8,
+		ndless_sys::scr_type_t_SCR_320x240_16 => Screen::Screen320x240x16,
+		ndless_sys::scr_type_t_SCR_320x240_565 => Screen::Screen320x240x565,
+		ndless_sys::scr_type_t_SCR_240x320_565 => Screen::Screen240x320x565,
+		ndless_sys::scr_type_t_SCR_320x240_555 => Screen::Screen320x240x555,
+		ndless_sys::scr_type_t_SCR_240x320_555 => Screen::Screen240x320x555,
+		_ => Screen::Unknown,
+	}
+}
+
+impl Screen {
+	fn raw(self) -> ndless_sys::scr_type_t {
+		match self {
+			Screen::Screen320x240x4 => ndless_sys::scr_type_t_SCR_320x240_4,
+			Screen::Screen320x240x8 => ndless_sys::scr_type_t_SCR_320x240_8,
+			Screen::Screen320x240x16 => ndless_sys::scr_type_t_SCR_320x240_16,
+			Screen::Screen320x240x565 => ndless_sys::scr_type_t_SCR_320x240_565,
+			Screen::Screen240x320x565 => ndless_sys::scr_type_t_SCR_240x320_565,
+			Screen::Screen320x240x555 => ndless_sys::scr_type_t_SCR_320x240_555,
+			Screen::Screen240x320x555 => ndless_sys::scr_type_t_SCR_240x320_555,
+			Screen::Unknown => ndless_sys::scr_type_t_SCR_TYPE_INVALID,
+		}
+	}
+}
+
+/// Switches the LCD to the mode of `screen`, returning `false` if the
+/// hardware doesn't support it. [`Screen::Unknown`] switches back to the
+/// mode of the OS, which is also restored when the program exits.
+///
+/// [`lcd_type`] returns the native mode, which doesn't need conversion.
+pub fn set_lcd_type(screen: Screen) -> bool {
+	unsafe { ndless_sys::lcd_init(screen.raw()) }
+}
+
+#[doc(hidden)]
+pub fn __init() {
+	if let Some(lcd) = regs::lcd() {
+		unsafe { ORIGINAL_CONTROL = Some(lcd.control().read()) };
+	}
+}
+
+/// Restores the screen mode that the program was started with, if it was
+/// left changed, e.g. by a panic while drawing
+#[doc(hidden)]
+pub fn __cleanup() {
+	if let Some(control) = unsafe { ORIGINAL_CONTROL } {
+		if lcd().control().read() != control {
+			unsafe { ndless_sys::lcd_init(ndless_sys::scr_type_t_SCR_TYPE_INVALID) };
+		}
+		unsafe { ORIGINAL_CONTROL = None };
+	}
+}
+
+/// Returns the registers of the LCD controller, panicking if they aren't known
+fn lcd() -> regs::Lcd {
+	regs::lcd().expect(regs::UNSUPPORTED)
+}
+
+/// The number of bits per pixel in memory for each value of the `LcdBpp` field
+/// of the control register. 24-bit colors take 32 bits, and 12-bit colors 16.
+const BITS_PER_PIXEL: [usize; 8] = [1, 2, 4, 8, 16, 32, 16, 16];
+
+/// Keeps what the program drew on the screen when it returns, instead of
+/// letting the OS redraw the screen. The mode of the LCD is still restored, so
+/// the image should be drawn in the mode of the OS.
+pub fn keep_on_exit() -> Result<(), Unsupported> {
+	crate::ndless::no_scr_redraw()
+}
+
+/// The contents and configuration of the LCD, restored when this is dropped:
+/// the framebuffer and its address, the color mode, the timings and the
+/// palette.
+#[must_use = "the screen is restored when the snapshot is dropped"]
+#[derive(Debug)]
+pub struct ScreenSnapshot {
+	control: u32,
+	timing: [u32; 4],
+	base: u32,
+	/// The palette, in paletted modes
+	palette: Option<Box<[u32; 128]>>,
+	pixels: Vec<u8>,
+}
+
+impl ScreenSnapshot {
+	/// Captures the screen that is displayed.
+	///
+	/// # Errors
+	///
+	/// Returns an error if there isn't enough memory for a copy of the
+	/// framebuffer.
+	///
+	/// # Panics
+	///
+	/// Panics on models whose LCD controller isn't known, such as the CX II,
+	/// see [`regs::is_supported`].
+	pub fn capture() -> Result<Self, TryReserveError> {
+		let lcd = lcd();
+		let control = lcd.control().read();
+		let mut timing = [0; 4];
+		for (n, timing) in timing.iter_mut().enumerate() {
+			*timing = lcd.timing(n).read();
+		}
+		let base = lcd.upper_base().read();
+		let mut snapshot = ScreenSnapshot {
+			control,
+			timing,
+			base,
+			palette: None,
+			pixels: Vec::new(),
+		};
+		let size = snapshot.width() * snapshot.height() * snapshot.bits_per_pixel() / 8;
+		snapshot.pixels.try_reserve_exact(size)?;
+		unsafe {
+			let framebuffer = core::slice::from_raw_parts(base as usize as *const u8, size);
+			snapshot.pixels.extend_from_slice(framebuffer);
+		}
+		if snapshot.bits_per_pixel() <= 8 {
+			let mut palette = Box::new([0; 128]);
+			for (n, colors) in palette.iter_mut().enumerate() {
+				*colors = lcd.palette(n).read();
+			}
+			snapshot.palette = Some(palette);
+		}
+		Ok(snapshot)
+	}
+
+	/// The width of the screen in pixels, as configured in the LCD controller
+	pub fn width(&self) -> usize {
+		(((self.timing[0] >> 2) & 0x3F) as usize + 1) * 16
+	}
+
+	/// The height of the screen in pixels, as configured in the LCD controller
+	pub fn height(&self) -> usize {
+		(self.timing[1] & 0x3FF) as usize + 1
+	}
+
+	/// The number of bits used by each pixel in the framebuffer
+	pub fn bits_per_pixel(&self) -> usize {
+		BITS_PER_PIXEL[((self.control >> 1) & 0b111) as usize]
+	}
+
+	/// The contents of the framebuffer, in the format of the captured mode
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
+	}
+
+	/// Puts the captured screen back, without waiting for the snapshot to be
+	/// dropped.
+	pub fn restore(&self) {
+		let lcd = lcd();
+		unsafe {
+			let framebuffer = self.base as usize as *mut u8;
+			framebuffer.copy_from_nonoverlapping(self.pixels.as_ptr(), self.pixels.len());
+			for (n, timing) in self.timing.iter().enumerate() {
+				lcd.timing(n).write(*timing);
+			}
+			if let Some(palette) = &self.palette {
+				for (n, colors) in palette.iter().enumerate() {
+					lcd.palette(n).write(*colors);
+				}
+			}
+			lcd.upper_base().write(self.base);
+			lcd.control().write(self.control);
+		}
+	}
+
+	/// Drops the snapshot without restoring the screen.
+	pub fn discard(self) {
+		let mut snapshot = core::mem::ManuallyDrop::new(self);
+		drop(core::mem::take(&mut snapshot.pixels));
+		drop(snapshot.palette.take());
+	}
+}
+
+impl Drop for ScreenSnapshot {
+	fn drop(&mut self) {
+		self.restore();
+	}
+}
@@ -0,0 +1,357 @@
+//! # Interrupts
+//! This module allows running Rust code when the hardware raises an interrupt,
+//! instead of polling. For example, a timer interrupt may be used for audio or
+//! precise frame pacing, and a keypad interrupt to sample input.
+//!
+//! The first call to [`register`] installs an IRQ handler in front of the
+//! OS's. Interrupts that have a handler registered are dispatched to it, and
+//! all others are passed on to the OS, which keeps working normally. The OS's
+//! vector table and interrupt controller configuration are restored when the
+//! program exits, even after a panic.
+//!
+//! Handlers run in IRQ mode, on the OS's small IRQ stack, with interrupts
+//! disabled. They should be short, must not allocate or panic, and must
+//! acknowledge the interrupt in the device that raised it, or it will be raised
+//! again immediately. Data shared with the rest of the program should be
+//! accessed in a critical section with [`free`].
+//!
+//! With the `critical-section` feature, this crate also provides an
+//! implementation for the [`critical-section`](https://docs.rs/critical-section)
+//! crate, so that libraries depending on it work on the calculator.
+
+use core::arch::{asm, global_asm};
+use core::fmt;
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+use crate::hw::regs;
+use crate::prelude::*;
+
+/// Address of the IRQ handler slot of the vector table. The IRQ exception
+/// vector at `0x18` jumps to the address stored here.
+const IRQ_HANDLER: usize = 0x38;
+/// Addresses of the exception handler slots saved while our handler is
+/// installed
+const VECTOR_SLOTS: core::ops::Range<usize> = 0x20..0x40;
+
+/// The IRQ disable bit of the CPSR
+const CPSR_DISABLE_IRQ: u32 = 0x80;
+
+type Handler = Box<dyn FnMut() + Send>;
+
+const NO_HANDLER: Option<Handler> = None;
+static mut HANDLERS: [Option<Handler>; 32] = [NO_HANDLER; 32];
+/// Interrupt lines that have a handler in [`HANDLERS`]
+static mut REGISTERED: u32 = 0;
+/// The vector table slots and enabled interrupts of the OS, saved when our
+/// handler is installed
+static mut ORIGINAL: Option<([u32; 8], u32)> = None;
+
+global_asm!(
+	".section .text.__ndless_irq_trampoline, \"ax\", %progbits",
+	".arm",
+	".global __ndless_irq_trampoline",
+	"__ndless_irq_trampoline:",
+	"stmfd sp!, {{r0-r3, r12, lr}}",
+	"bl __ndless_irq_dispatch",
+	"cmp r0, #0",
+	"ldmfd sp!, {{r0-r3, r12, lr}}",
+	// Some interrupts are left for the OS: jump to its handler, which returns
+	// from the exception itself
+	"ldrne pc, __ndless_os_irq_handler",
+	"subs pc, lr, #4",
+	".global __ndless_os_irq_handler",
+	"__ndless_os_irq_handler:",
+	".word 0",
+);
+
+extern "C" {
+	fn __ndless_irq_trampoline();
+	static mut __ndless_os_irq_handler: u32;
+}
+
+/// Calls the registered handlers for pending interrupts. Returns `true` if
+/// other interrupts are pending and must be handled by the OS.
+#[no_mangle]
+extern "C" fn __ndless_irq_dispatch() -> bool {
+	let status = controller().status().read();
+	unsafe {
+		let registered = REGISTERED;
+		let mut pending = status & registered;
+		while pending != 0 {
+			let line = pending.trailing_zeros() as usize;
+			if let Some(handler) = &mut (*addr_of_mut!(HANDLERS))[line] {
+				handler();
+			}
+			pending &= !(1 << line);
+		}
+		status & !registered != 0
+	}
+}
+
+/// An interrupt line of the interrupt controller
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum Irq {
+	/// The serial port
+	Uart,
+	/// The watchdog timer
+	Watchdog,
+	/// The real-time clock alarm, see [`hw::rtc`][crate::hw::rtc]
+	Rtc,
+	/// The USB OTG controller
+	Usb,
+	/// The analog-to-digital converter
+	Adc,
+	/// The keypad, when a key is pressed or released
+	Keypad,
+	/// The timer at [`TimerId::Fast`][crate::hw::regs::TimerId::Fast]
+	FastTimer,
+	/// The timer at [`TimerId::First`][crate::hw::regs::TimerId::First]
+	FirstTimer,
+	/// The timer at [`TimerId::Second`][crate::hw::regs::TimerId::Second],
+	/// also used by [`configure_sleep`][crate::timer::configure_sleep]
+	SecondTimer,
+	/// The LCD controller, e.g. at the start of each frame
+	Lcd,
+	/// Any interrupt line, by number
+	Other(u8),
+}
+
+impl Irq {
+	/// The bit of the interrupt line in the interrupt controller registers
+	pub fn line(self) -> u8 {
+		match self {
+			Irq::Uart => 1,
+			Irq::Watchdog => 3,
+			Irq::Rtc => 4,
+			Irq::Usb => 8,
+			Irq::Adc => 11,
+			Irq::Keypad => 16,
+			Irq::FastTimer => 17,
+			Irq::FirstTimer => 18,
+			Irq::SecondTimer => 19,
+			Irq::Lcd => 21,
+			Irq::Other(line) => line,
+		}
+	}
+}
+
+/// Returned by [`register`] when the interrupt already has a handler
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct AlreadyRegistered(pub Irq);
+
+impl fmt::Display for AlreadyRegistered {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "a handler is already registered for {:?}", self.0)
+	}
+}
+
+impl crate::error::Error for AlreadyRegistered {}
+
+/// Returns the registers of the interrupt controller, panicking if they aren't
+/// known
+fn controller() -> regs::InterruptController {
+	regs::interrupt_controller().expect(regs::UNSUPPORTED)
+}
+
+/// A registered interrupt handler. The handler is removed when this is
+/// dropped, and the interrupt is given back to the OS.
+#[must_use = "the handler is removed when the interrupt is dropped"]
+#[derive(Debug)]
+pub struct Interrupt {
+	irq: Irq,
+}
+
+impl Interrupt {
+	pub fn irq(&self) -> Irq {
+		self.irq
+	}
+}
+
+impl Drop for Interrupt {
+	fn drop(&mut self) {
+		let line = self.irq.line();
+		let handler = free(|_| unsafe {
+			let bit = 1 << line;
+			if let Some((_, os_enabled)) = ORIGINAL {
+				if os_enabled & bit == 0 {
+					controller().enable_clear().write(bit);
+				}
+			}
+			REGISTERED &= !bit;
+			(*addr_of_mut!(HANDLERS))[line as usize].take()
+		});
+		drop(handler);
+	}
+}
+
+/// Registers `handler` to be called when `irq` is raised, and enables the
+/// interrupt. While registered, the OS doesn't receive this interrupt.
+///
+/// The handler must acknowledge the interrupt in the device: see the
+/// [module-level documentation][self] for restrictions.
+///
+/// # Errors
+///
+/// Returns an error if a handler is already registered for `irq`.
+///
+/// # Panics
+///
+/// Panics on models whose interrupt controller isn't known, such as the CX II,
+/// see [`regs::is_supported`].
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU32, Ordering};
+/// use ndless::hw::regs::{self, Timer, TimerId};
+/// use ndless::interrupt::{self, Irq};
+///
+/// static FRAMES: AtomicU32 = AtomicU32::new(0);
+///
+/// let _timer = interrupt::register(Irq::FastTimer, || {
+/// 	if let Some(Timer::Sp804(timer)) = regs::timer(TimerId::Fast) {
+/// 		unsafe { timer.int_clear().write(1) };
+/// 	}
+/// 	FRAMES.fetch_add(1, Ordering::Relaxed);
+/// })
+/// .unwrap();
+/// ```
+pub fn register(
+	irq: Irq,
+	handler: impl FnMut() + Send + 'static,
+) -> Result<Interrupt, AlreadyRegistered> {
+	let line = irq.line();
+	assert!(line < 32, "there are only 32 interrupt lines");
+	let handler: Handler = Box::new(handler);
+	let controller = controller();
+	free(|_| unsafe {
+		let bit = 1 << line;
+		if REGISTERED & bit != 0 {
+			return Err(AlreadyRegistered(irq));
+		}
+		install();
+		(*addr_of_mut!(HANDLERS))[line as usize] = Some(handler);
+		REGISTERED |= bit;
+		controller.enable().write(bit);
+		Ok(Interrupt { irq })
+	})
+}
+
+/// Installs our handler in front of the OS's, if it wasn't already. Must be
+/// called with interrupts disabled.
+unsafe fn install() {
+	let original = ORIGINAL;
+	if original.is_some() {
+		return;
+	}
+	let mut vectors = [0; 8];
+	for (vector, addr) in vectors.iter_mut().zip(VECTOR_SLOTS.step_by(4)) {
+		*vector = read_volatile(addr as *const u32);
+	}
+	ORIGINAL = Some((vectors, controller().enable().read()));
+	__ndless_os_irq_handler = read_volatile(IRQ_HANDLER as *const u32);
+	write_volatile(
+		IRQ_HANDLER as *mut u32,
+		__ndless_irq_trampoline as unsafe extern "C" fn() as usize as u32,
+	);
+}
+
+/// Restores the vector table and interrupt controller configuration of the OS
+#[doc(hidden)]
+pub fn __cleanup() {
+	free(|_| unsafe {
+		if let Some((vectors, enabled)) = ORIGINAL {
+			let controller = controller();
+			controller.enable_clear().write(!enabled);
+			controller.enable().write(enabled);
+			for (vector, addr) in vectors.iter().zip(VECTOR_SLOTS.step_by(4)) {
+				write_volatile(addr as *mut u32, *vector);
+			}
+			REGISTERED = 0;
+			ORIGINAL = None;
+		}
+	});
+	for handler in unsafe { (*addr_of_mut!(HANDLERS)).iter_mut() } {
+		drop(handler.take());
+	}
+	// The OS runs with IRQs enabled, but they may have been left disabled by
+	// a panic in a critical section
+	unsafe { restore(true) };
+}
+
+/// A token proving that interrupts are disabled, passed to the closure of
+/// [`free`]
+#[derive(Debug)]
+pub struct CriticalSection {
+	_private: (),
+}
+
+/// Returns `true` if IRQs are enabled.
+pub fn is_enabled() -> bool {
+	cpsr() & CPSR_DISABLE_IRQ == 0
+}
+
+fn cpsr() -> u32 {
+	let cpsr: u32;
+	unsafe { asm!("mrs {}, cpsr", out(reg) cpsr) };
+	cpsr
+}
+
+/// Disables IRQs, returning whether they were enabled before.
+///
+/// # Safety
+///
+/// Must be paired with a call to [`restore`], or the OS will stop working.
+pub unsafe fn disable() -> bool {
+	let cpsr = cpsr();
+	asm!("msr cpsr_c, {}", in(reg) cpsr | CPSR_DISABLE_IRQ);
+	cpsr & CPSR_DISABLE_IRQ == 0
+}
+
+/// Enables IRQs again if `was_enabled`, as returned by [`disable`].
+///
+/// # Safety
+///
+/// Must only be called at the end of a critical section started by
+/// [`disable`].
+pub unsafe fn restore(was_enabled: bool) {
+	if was_enabled {
+		asm!("msr cpsr_c, {}", in(reg) cpsr() & !CPSR_DISABLE_IRQ);
+	}
+}
+
+/// Runs `f` with IRQs disabled, so that interrupt handlers can't run in the
+/// middle of it. Critical sections may be nested.
+///
+/// ```
+/// use core::cell::Cell;
+/// use ndless::interrupt;
+///
+/// let counter = Cell::new(0);
+/// interrupt::free(|_| counter.set(counter.get() + 1));
+/// ```
+pub fn free<R>(f: impl FnOnce(&CriticalSection) -> R) -> R {
+	unsafe {
+		let was_enabled = disable();
+		let result = f(&CriticalSection { _private: () });
+		restore(was_enabled);
+		result
+	}
+}
+
+#[cfg(feature = "critical-section")]
+mod critical_section_impl {
+	struct NdlessCriticalSection;
+	critical_section::set_impl!(NdlessCriticalSection);
+
+	unsafe impl critical_section::Impl for NdlessCriticalSection {
+		unsafe fn acquire() -> critical_section::RawRestoreState {
+			super::disable()
+		}
+
+		unsafe fn release(was_enabled: critical_section::RawRestoreState) {
+			super::restore(was_enabled)
+		}
+	}
+}
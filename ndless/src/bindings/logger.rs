@@ -0,0 +1,232 @@
+//! # Logging
+//! This module implements a backend for the [`log`](https://docs.rs/log)
+//! crate, so that messages from libraries using its macros are displayed on
+//! the calculator. It is only available with the `log` feature of this crate.
+//!
+//! Messages may be sent to any combination of sinks, selected with a
+//! [`Logger`]:
+//!
+//! * stdout, which is the serial port
+//! * a file, which is rotated when it becomes too big
+//! * an in-memory ring buffer, returned by [`recent`], e.g. to be displayed by
+//!   an on-screen overlay
+//! * a message box, usually only for errors
+//!
+//! Each record is timestamped with [`get_ticks`][crate::timer::get_ticks].
+//!
+//! ```
+//! use ndless::env;
+//! use ndless::logger::{Level, LevelFilter, Logger};
+//!
+//! Logger::new()
+//! 	.stdout()
+//! 	.file(env::get_documents_dir()?.join("log.txt.tns"), 16 * 1024)
+//! 	.message_box(Level::Error)
+//! 	.init(LevelFilter::Info)
+//! 	.unwrap();
+//! log::info!("started");
+//! ```
+
+use alloc::collections::VecDeque;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+pub use log::{Level, LevelFilter, SetLoggerError};
+
+use crate::fs::{self, OpenOptions};
+use crate::interrupt;
+use crate::io::Write;
+use crate::path::{Path, PathBuf};
+use crate::prelude::*;
+use crate::timer::{get_ticks, TICKS_PER_SECOND};
+
+/// The last records, for the in-memory sink
+struct Memory {
+	entries: VecDeque<Entry>,
+	capacity: usize,
+}
+
+static mut MEMORY: Memory = Memory {
+	entries: VecDeque::new(),
+	capacity: 0,
+};
+
+fn with_memory<R>(f: impl FnOnce(&mut Memory) -> R) -> R {
+	interrupt::free(|_| f(unsafe { &mut *addr_of_mut!(MEMORY) }))
+}
+
+/// A log record, as kept by the in-memory sink
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct Entry {
+	/// The value of [`get_ticks`][crate::timer::get_ticks] when the record was
+	/// logged
+	pub ticks: u32,
+	pub level: Level,
+	/// The module that logged the record, unless a target was specified
+	pub target: String,
+	pub message: String,
+}
+
+impl fmt::Display for Entry {
+	/// Formats the entry as `[seconds.milliseconds LEVEL target] message`.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"[{:>4}.{:03} {:<5} {}] {}",
+			self.ticks / TICKS_PER_SECOND,
+			self.ticks % TICKS_PER_SECOND * 1000 / TICKS_PER_SECOND,
+			self.level,
+			self.target,
+			self.message
+		)
+	}
+}
+
+/// A [`log::Log`] implementation, configured with the sinks that records are
+/// sent to. Install it with [`Logger::init`].
+#[derive(Debug, Default)]
+pub struct Logger {
+	stdout: bool,
+	file: Option<(PathBuf, u64)>,
+	memory: usize,
+	message_box: Option<Level>,
+}
+
+impl Logger {
+	/// Creates a logger without any sinks.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Writes records to stdout, which is the serial port.
+	pub fn stdout(mut self) -> Self {
+		self.stdout = true;
+		self
+	}
+
+	/// Appends records to the file at `path`. When it would become longer than
+	/// `max_len` bytes, it is renamed with an `.old` extension, replacing the
+	/// previous one, and a new file is started. For example, `log.txt.tns` is
+	/// renamed to `log.txt.old.tns`.
+	///
+	/// The file is opened and closed for each record, so that it is complete
+	/// if the program crashes.
+	pub fn file(mut self, path: impl Into<PathBuf>, max_len: u64) -> Self {
+		self.file = Some((path.into(), max_len));
+		self
+	}
+
+	/// Keeps the last `capacity` records in memory, to be returned by
+	/// [`recent`].
+	pub fn memory(mut self, capacity: usize) -> Self {
+		self.memory = capacity;
+		self
+	}
+
+	/// Displays records of `level` or more severe in a message box, which
+	/// blocks until the user closes it.
+	pub fn message_box(mut self, level: Level) -> Self {
+		self.message_box = Some(level);
+		self
+	}
+
+	/// Installs this logger for the [`log`](https://docs.rs/log) macros, only
+	/// logging records of `level` or more severe. The level may be changed
+	/// later with [`set_level`].
+	///
+	/// # Errors
+	///
+	/// Returns an error if a logger was already installed.
+	pub fn init(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+		let capacity = self.memory;
+		log::set_logger(Box::leak(Box::new(self)))?;
+		with_memory(|memory| {
+			memory.capacity = capacity;
+			memory.entries = VecDeque::with_capacity(capacity);
+		});
+		log::set_max_level(level);
+		Ok(())
+	}
+
+	fn write_file(path: &Path, max_len: u64, entry: &Entry) -> crate::io::Result<()> {
+		let line = format!("{}\n", entry);
+		if let Ok(metadata) = fs::metadata(path) {
+			if metadata.len() + line.len() as u64 > max_len {
+				let rotated = match path.extension() {
+					Some(ext) if ext == "tns" => path.with_extension("old.tns"),
+					_ => path.with_extension("old"),
+				};
+				let _ = fs::remove_file(&rotated);
+				fs::rename(path, rotated)?;
+			}
+		}
+		OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)?
+			.write_all(line.as_bytes())
+	}
+}
+
+impl log::Log for Logger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.level() <= log::max_level()
+	}
+
+	fn log(&self, record: &log::Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		let entry = Entry {
+			ticks: get_ticks(),
+			level: record.level(),
+			target: record.target().into(),
+			message: format!("{}", record.args()),
+		};
+		if self.stdout {
+			crate::println!("{}", entry);
+		}
+		if let Some((path, max_len)) = &self.file {
+			let _ = Self::write_file(path, *max_len, &entry);
+		}
+		if matches!(self.message_box, Some(level) if entry.level <= level) {
+			crate::msg::msg(
+				&format!("{} in {}", entry.level, entry.target),
+				&entry.message,
+			);
+		}
+		if self.memory > 0 {
+			with_memory(|memory| {
+				if memory.entries.len() >= memory.capacity {
+					memory.entries.pop_front();
+				}
+				memory.entries.push_back(entry);
+			});
+		}
+	}
+
+	fn flush(&self) {
+		let _ = crate::io::stdout().flush();
+	}
+}
+
+/// Changes the most verbose level of records that are logged.
+pub fn set_level(level: LevelFilter) {
+	log::set_max_level(level);
+}
+
+/// Returns the most verbose level of records that are logged.
+pub fn level() -> LevelFilter {
+	log::max_level()
+}
+
+/// Returns the records kept by the in-memory sink, oldest first. See
+/// [`Logger::memory`].
+pub fn recent() -> Vec<Entry> {
+	with_memory(|memory| memory.entries.iter().cloned().collect())
+}
+
+/// Removes the records kept by the in-memory sink.
+pub fn clear() {
+	with_memory(|memory| memory.entries.clear());
+}
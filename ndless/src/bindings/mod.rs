@@ -1,10 +1,24 @@
+pub mod args;
+pub mod config;
+pub mod crash;
 pub mod env;
 pub mod hw;
 pub mod input;
+pub mod interrupt;
+#[cfg(feature = "log")]
+pub mod logger;
 pub mod math;
 pub mod msg;
 pub mod ndless;
+pub mod oom;
+pub mod os;
 pub mod out;
+#[cfg(feature = "unwind")]
+pub mod panic;
 pub mod process;
+pub mod profile;
+pub mod resident;
+pub mod serial;
+pub mod syscall;
 pub mod thread;
 pub mod timer;
@@ -1,7 +1,9 @@
 //! # Message boxes
 //! This module contains functions to display message boxes to the user.
 
+use core::fmt;
 use core::mem::transmute;
+use core::ops::RangeInclusive;
 use core::slice;
 
 use cstr_core::CStr;
@@ -9,6 +11,7 @@ use ndless_sys::_show_msgbox;
 
 use crate::cstr;
 use crate::prelude::*;
+use crate::syscall::{require, Syscall, Unsupported};
 
 #[repr(u32)]
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
@@ -63,63 +66,284 @@ pub fn msg_3b(title: &str, msg: &str, btn1: &str, btn2: &str, btn3: &str) -> But
 	}
 }
 
+/// Returned by the input dialogs when no usable value was entered
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum InputError {
+	/// The user pressed escape or closed the dialog.
+	Cancelled,
+	/// The entered value could not be parsed, or was outside of the allowed
+	/// range.
+	Invalid,
+	/// The installed version of Ndless doesn't support this dialog.
+	Unsupported(Unsupported),
+}
+
+impl From<Unsupported> for InputError {
+	fn from(err: Unsupported) -> Self {
+		InputError::Unsupported(err)
+	}
+}
+
+impl fmt::Display for InputError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			InputError::Cancelled => f.write_str("the dialog was cancelled"),
+			InputError::Invalid => f.write_str("the entered value is invalid"),
+			InputError::Unsupported(err) => err.fmt(f),
+		}
+	}
+}
+
+impl crate::error::Error for InputError {}
+
+/// The range of values accepted by a numeric input dialog, as well as the
+/// value that is initially shown.
+///
+/// The OS cancels numeric popups whose minimum is 0 or below. To work around
+/// this, ranges starting below 1 are shifted so that they start at 1 before
+/// being passed to the OS, and the entered value is shifted back before being
+/// returned. This means that for these ranges, the dialog displays values
+/// offset by `1 - min`.
+///
+/// Any `RangeInclusive` may be converted into a `NumericInput`:
+///
+/// ```
+/// use ndless::msg::{msg_numeric, NumericInput};
+///
+/// let temperature = msg_numeric("Thermostat", "Setup", "Offset", -10..=10);
+/// let brightness = msg_numeric(
+/// 	"Display",
+/// 	"Setup",
+/// 	"Brightness",
+/// 	NumericInput::new(0, 100).with_default(50),
+/// );
+/// ```
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct NumericInput<T> {
+	min: T,
+	max: T,
+	default: T,
+}
+
+impl<T: PartialOrd + Copy> NumericInput<T> {
+	/// Creates an input accepting values from `min` to `max`, both inclusive.
+	/// The default value is `min`.
+	///
+	/// # Panics
+	///
+	/// Panics if `min` is greater than `max`, or if either is NaN.
+	pub fn new(min: T, max: T) -> Self {
+		assert!(
+			min <= max,
+			"the minimum must not be greater than the maximum"
+		);
+		NumericInput {
+			min,
+			max,
+			default: min,
+		}
+	}
+
+	/// Sets the value that is initially shown in the dialog.
+	///
+	/// # Panics
+	///
+	/// Panics if `default` is outside of the accepted range.
+	pub fn with_default(mut self, default: T) -> Self {
+		assert!(
+			self.contains(default),
+			"the default value must be inside of the range"
+		);
+		self.default = default;
+		self
+	}
+
+	/// The smallest accepted value
+	pub fn min(&self) -> T {
+		self.min
+	}
+
+	/// The largest accepted value
+	pub fn max(&self) -> T {
+		self.max
+	}
+
+	/// The value that is initially shown in the dialog, `min` unless set with
+	/// [`with_default`][NumericInput::with_default]
+	pub fn default_value(&self) -> T {
+		self.default
+	}
+
+	/// Returns `true` if `value` is accepted by this input.
+	pub fn contains(&self, value: T) -> bool {
+		self.min <= value && value <= self.max
+	}
+
+	fn check(&self, value: T) -> Result<T, InputError> {
+		if self.contains(value) {
+			Ok(value)
+		} else {
+			Err(InputError::Invalid)
+		}
+	}
+}
+
+impl<T: PartialOrd + Copy> From<RangeInclusive<T>> for NumericInput<T> {
+	fn from(range: RangeInclusive<T>) -> Self {
+		NumericInput::new(*range.start(), *range.end())
+	}
+}
+
+impl NumericInput<i32> {
+	/// The amount added to values before being passed to the OS
+	fn offset(&self) -> i64 {
+		if self.min < 1 {
+			1 - self.min as i64
+		} else {
+			0
+		}
+	}
+
+	/// Converts a value into the range passed to the OS. Values that don't fit
+	/// after shifting are saturated, which only happens for ranges spanning
+	/// more than `i32::MAX` numbers.
+	fn os_value(&self, value: i32) -> i32 {
+		(value as i64 + self.offset()).min(i32::MAX as i64) as i32
+	}
+
+	/// Converts a value returned by the OS back into the range of this input.
+	fn user_value(&self, value: i32) -> Result<i32, InputError> {
+		let value = value as i64 - self.offset();
+		if value < i32::MIN as i64 {
+			return Err(InputError::Invalid);
+		}
+		self.check(value as i32)
+	}
+}
+
 /// Creates a dialog box with a numerical input
-pub fn msg_numeric(title: &str, subtitle: &str, msg: &str, range: (i32, i32)) -> Option<i32> {
+///
+/// Returns [`InputError::Cancelled`] if the user closes the dialog. See
+/// [`NumericInput`] for details on how ranges including 0 or negative numbers
+/// are displayed.
+pub fn msg_numeric(
+	title: &str,
+	subtitle: &str,
+	msg: &str,
+	input: impl Into<NumericInput<i32>>,
+) -> Result<i32, InputError> {
+	require(Syscall::NumericInput)?;
+	let input = input.into();
 	let title = cstr!(title);
 	let subtitle = cstr!(subtitle);
 	let msg = cstr!(msg);
-	let mut num = 0i32;
+	let mut num = input.os_value(input.default);
 	match unsafe {
 		ndless_sys::show_1numeric_input(
 			title.as_ptr(),
 			subtitle.as_ptr(),
 			msg.as_ptr(),
 			&mut num,
-			range.0,
-			range.1,
+			input.os_value(input.min),
+			input.os_value(input.max),
 		)
 	} {
-		1 => Some(num),
-		_ => None,
+		1 => input.user_value(num),
+		_ => Err(InputError::Cancelled),
 	}
 }
 
 /// Creates a dialog box with two numerical inputs
+///
+/// Returns [`InputError::Cancelled`] if the user closes the dialog. See
+/// [`NumericInput`] for details on how ranges including 0 or negative numbers
+/// are displayed.
 pub fn msg_2numeric(
 	title: &str,
 	subtitle: &str,
 	msg1: &str,
-	range1: (i32, i32),
+	input1: impl Into<NumericInput<i32>>,
 	msg2: &str,
-	range2: (i32, i32),
-) -> Option<(i32, i32)> {
+	input2: impl Into<NumericInput<i32>>,
+) -> Result<(i32, i32), InputError> {
+	require(Syscall::NumericInput)?;
+	let input1 = input1.into();
+	let input2 = input2.into();
 	let title = cstr!(title);
 	let subtitle = cstr!(subtitle);
 	let msg1 = cstr!(msg1);
 	let msg2 = cstr!(msg2);
-	let mut num1 = 0i32;
-	let mut num2 = 0i32;
+	let mut num1 = input1.os_value(input1.default);
+	let mut num2 = input2.os_value(input2.default);
 	match unsafe {
 		ndless_sys::show_2numeric_input(
 			title.as_ptr(),
 			subtitle.as_ptr(),
 			msg1.as_ptr(),
 			&mut num1,
-			range1.0,
-			range1.1,
+			input1.os_value(input1.min),
+			input1.os_value(input1.max),
 			msg2.as_ptr(),
 			&mut num2,
-			range2.0,
-			range2.1,
+			input2.os_value(input2.min),
+			input2.os_value(input2.max),
 		)
 	} {
-		1 => Some((num1, num2)),
-		_ => None,
+		1 => Ok((input1.user_value(num1)?, input2.user_value(num2)?)),
+		_ => Err(InputError::Cancelled),
 	}
 }
 
+/// Creates a dialog box with a decimal number input
+///
+/// The OS has no dialog for decimal numbers, so this uses a text input and
+/// parses the result. Returns [`InputError::Invalid`] if the text isn't a
+/// number or is outside of the range, and [`InputError::Cancelled`] if the
+/// user closes the dialog.
+///
+/// ```
+/// use ndless::msg::{msg_float, NumericInput};
+///
+/// let scale = msg_float("Plot", "Scale", NumericInput::new(0.1, 10.).with_default(1.));
+/// ```
+pub fn msg_float(
+	title: &str,
+	msg: &str,
+	input: impl Into<NumericInput<f64>>,
+) -> Result<f64, InputError> {
+	let input = input.into();
+	let text = try_msg_input(title, msg, &format!("{}", input.default))?;
+	let value = text
+		.trim()
+		.parse::<f64>()
+		.map_err(|_| InputError::Invalid)?;
+	input.check(value)
+}
+
 /// Creates a dialog box with a text input
+///
+/// Returns `None` if the user closes the dialog or enters an empty text.
+///
+/// # Panics
+///
+/// Panics if the installed version of Ndless doesn't support this dialog. Use
+/// [`try_msg_input`] to handle this case.
 pub fn msg_input(title: &str, msg: &str, default: &str) -> Option<String> {
+	match try_msg_input(title, msg, default) {
+		Ok(text) => Some(text),
+		Err(InputError::Unsupported(err)) => panic!("{}", err),
+		Err(_) => None,
+	}
+}
+
+/// Creates a dialog box with a text input, like [`msg_input`], but returns an
+/// error instead of panicking if the installed version of Ndless doesn't
+/// support it.
+///
+/// Returns [`InputError::Cancelled`] if the user closes the dialog or enters
+/// an empty text.
+pub fn try_msg_input(title: &str, msg: &str, default: &str) -> Result<String, InputError> {
+	require(Syscall::MsgUserInput)?;
 	let title = cstr!(title);
 	let msg = cstr!(msg);
 	let default = cstr!(default);
@@ -127,16 +351,14 @@ pub fn msg_input(title: &str, msg: &str, default: &str) -> Option<String> {
 	let ret = match unsafe {
 		ndless_sys::show_msg_user_input(title.as_ptr(), msg.as_ptr(), default.as_ptr(), &mut ptr)
 	} {
-		-1 => None,
+		-1 => Err(InputError::Cancelled),
 		len => unsafe {
-			Some(
-				CStr::from_bytes_with_nul_unchecked(slice::from_raw_parts(
-					ptr as *const u8,
-					len as usize + 1,
-				))
-				.to_string_lossy()
-				.into_owned(),
-			)
+			Ok(CStr::from_bytes_with_nul_unchecked(slice::from_raw_parts(
+				ptr as *const u8,
+				len as usize + 1,
+			))
+			.to_string_lossy()
+			.into_owned())
 		},
 	};
 	unsafe { ndless_sys::free(ptr as *mut cty::c_void) };
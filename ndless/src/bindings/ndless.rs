@@ -1,12 +1,16 @@
 //! # Various ndless-related functions
 //! This module contains functions that configure miscellaneous settings used in ndless.
 
+use crate::syscall::{is_available, require, Syscall, Unsupported};
+
 pub fn assert_ndless_rev(required_version: u32) {
 	unsafe { ndless_sys::assert_ndless_rev(required_version) }
 }
 
+/// Returns `true` if the program is being run at OS startup. Always `false`
+/// on versions of Ndless that don't support startup programs.
 pub fn is_startup() -> bool {
-	unsafe { ndless_sys::nl_isstartup() > 0 }
+	is_available(Syscall::IsStartup) && unsafe { ndless_sys::nl_isstartup() > 0 }
 }
 
 
@@ -22,7 +26,19 @@ pub fn bkpt() {
 
 /// See
 /// [Hackspire](https://hackspire.org/index.php/Ndless_features_and_limitations#Resident_programs)
+///
+/// # Panics
+///
+/// Panics if the installed version of Ndless doesn't support resident
+/// programs. Use [`try_set_resident`] to handle this case.
 pub fn set_resident() {
+	try_set_resident().unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Makes the program resident like [`set_resident`], or returns an error if
+/// the installed version of Ndless doesn't support it.
+pub fn try_set_resident() -> Result<(), Unsupported> {
+	require(Syscall::SetResident)?;
 	unsafe {
 		if ndless_static_vars::PROGRAM_STATE == ndless_static_vars::ProgramState::Normal {
 			ndless_sys::nl_set_resident();
@@ -30,15 +46,57 @@ pub fn set_resident() {
 			ndless_static_vars::PROGRAM_STATE = ndless_static_vars::ProgramState::Resident;
 		}
 	}
+	Ok(())
 }
 
-/// Must be called at the end of a program that creates or deletes files,
-/// to update the OS document browser.
+/// Keeps what the program drew on the screen when it exits, instead of
+/// redrawing the screen of the OS. See also [`hw::screen`][crate::hw::screen].
+pub fn no_scr_redraw() -> Result<(), Unsupported> {
+	require(Syscall::NoScrRedraw)?;
+	unsafe { nl_no_scr_redraw() };
+	Ok(())
+}
+
+/// `ndless-sys` doesn't have this syscall, so it is made with `swi` like the
+/// Ndless headers do.
+unsafe fn nl_no_scr_redraw() {
+	asm!(
+		"swi #{nr}",
+		nr = const 0x20_0007,
+		out("r0") _,
+		out("r1") _,
+		out("r2") _,
+		out("r3") _,
+		out("r4") _,
+		out("r12") _,
+		out("lr") _,
+	);
+}
+
+/// Updates the OS document browser after creating or deleting files. This is
+/// done when the program exits if it modified files with [`fs`][crate::fs]:
+/// see [`fs::set_refresh_at_exit`][crate::fs::set_refresh_at_exit].
 pub fn refresh_documents() {
 	unsafe { ndless_sys::refresh_osscr() }
 }
 
+/// Redraws the home screen of the OS, e.g. after changing its list of recent
+/// documents.
+pub fn refresh_home_screen() {
+	unsafe { ndless_sys::refresh_homescr() }
+}
+
+/// Reloads the list of files of the OS document browser. Prefer
+/// [`refresh_documents`], which is what Ndless recommends after changing files.
+pub fn refresh_doc_browser() {
+	// The meaning of the argument is undocumented
+	unsafe { ndless_sys::refresh_docbrowser(0) }
+}
+
 /// return true if a third-party Launcher was used to boot the OS, such as nLaunch/nLaunchy
+///
+/// Always `false` on versions of Ndless that can't detect this.
 pub fn third_party_loader() -> bool {
-	unsafe { ndless_sys::nl_loaded_by_3rd_party_loader() > 0 }
+	is_available(Syscall::LoadedBy3rdPartyLoader)
+		&& unsafe { ndless_sys::nl_loaded_by_3rd_party_loader() > 0 }
 }
@@ -0,0 +1,123 @@
+//! # Out of memory handling
+//! This module contains tools to handle running out of memory.
+//!
+//! When an allocation fails, the allocator of `ndless-handler` first calls the
+//! handler set with [`set_emergency_handler`], which may free memory and retry.
+//! If it still fails, fallible functions such as [`Vec::try_reserve`] return an
+//! error, and other allocations are reported like a panic, with the size of the
+//! allocation and how much memory is used: see [`crash`][crate::crash].
+//! Reporting needs some memory too, which may be kept aside with
+//! [`emergency_reserve`].
+//!
+//! ```
+//! use core::ptr::addr_of_mut;
+//! use ndless::oom;
+//! use ndless::prelude::*;
+//!
+//! static mut CACHE: Vec<Vec<u8>> = Vec::new();
+//!
+//! oom::emergency_reserve(16 * 1024)?;
+//! oom::set_emergency_handler(|_layout| {
+//! 	let cache = unsafe { &mut *addr_of_mut!(CACHE) };
+//! 	let freed = !cache.is_empty();
+//! 	cache.clear();
+//! 	freed
+//! });
+//! ```
+
+use alloc::collections::TryReserveError;
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+use crate::prelude::*;
+
+type EmergencyHandler = Box<dyn FnMut(Layout) -> bool>;
+
+static mut RESERVE: Vec<u8> = Vec::new();
+static mut EMERGENCY_HANDLER: Option<EmergencyHandler> = None;
+
+/// An allocation that failed, reported when the program runs out of memory
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct OutOfMemory {
+	/// The layout of the allocation
+	pub layout: Layout,
+	/// The number of bytes allocated by the program, if known
+	pub used: Option<usize>,
+	/// The size of the heap in bytes, if known: the soft limit with the
+	/// `alloc-stats` feature of `ndless-handler`, or the arena with `tlsf`
+	pub heap: Option<usize>,
+}
+
+impl fmt::Display for OutOfMemory {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"Out of memory: couldn't allocate {} bytes aligned to {}",
+			self.layout.size(),
+			self.layout.align()
+		)?;
+		if let Some(used) = self.used {
+			write!(f, "\n{} bytes used", used)?;
+			if let Some(heap) = self.heap {
+				write!(f, " of {}", heap)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Keeps `bytes` allocated, and frees them when the program runs out of
+/// memory, so that the failure can be reported. This replaces the previous
+/// reserve, and `0` removes it.
+pub fn emergency_reserve(bytes: usize) -> Result<(), TryReserveError> {
+	let reserve = unsafe { &mut *addr_of_mut!(RESERVE) };
+	*reserve = Vec::new();
+	reserve.try_reserve_exact(bytes)
+}
+
+/// Sets the function called when an allocation fails, with its layout.
+///
+/// It may free memory, such as caches, and return `true` to retry the
+/// allocation, or `false` to make it fail. It should only return `true` if it
+/// freed something, or the allocation is retried forever. Allocations that fail
+/// while it runs fail immediately.
+pub fn set_emergency_handler(handler: impl FnMut(Layout) -> bool + 'static) {
+	unsafe { EMERGENCY_HANDLER = Some(Box::new(handler)) };
+}
+
+/// Calls the emergency handler, returning whether to retry the allocation.
+/// Called by the allocator of `ndless-handler`.
+#[doc(hidden)]
+pub fn __retry(layout: Layout) -> bool {
+	let slot = unsafe { &mut *addr_of_mut!(EMERGENCY_HANDLER) };
+	match slot.take() {
+		Some(mut handler) => {
+			let retry = handler(layout);
+			// Keep the handler unless it set another one
+			let slot = unsafe { &mut *addr_of_mut!(EMERGENCY_HANDLER) };
+			if slot.is_none() {
+				*slot = Some(handler);
+			}
+			retry
+		}
+		None => false,
+	}
+}
+
+/// Frees the emergency reserve and reports the failure like a panic. Called by
+/// the out-of-memory handler of `ndless-handler`.
+#[doc(hidden)]
+pub fn __out_of_memory(info: OutOfMemory) -> ! {
+	unsafe { RESERVE = Vec::new() };
+	crate::crash::__panic(crate::crash::CrashInfo {
+		message: Some(info.to_string()),
+		location: None,
+	})
+}
+
+/// Frees the emergency reserve
+#[doc(hidden)]
+pub fn __cleanup() {
+	unsafe { RESERVE = Vec::new() };
+}
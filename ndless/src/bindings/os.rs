@@ -0,0 +1,189 @@
+//! # OS and Ndless versions
+//! This module contains functions to find out which OS and Ndless revision the
+//! program is running on, to enable features depending on the firmware.
+
+use core::fmt;
+
+use crate::hw::Model;
+
+/// A version of the TI-Nspire OS.
+///
+/// Use [`at_least`][OsVersion::at_least] to check for a minimum OS version:
+///
+/// ```
+/// use ndless::os;
+///
+/// if os::version().map_or(false, |version| version.at_least(3, 9, 0)) {
+/// 	// use features only available in 3.9 and above
+/// }
+/// ```
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct OsVersion {
+	pub major: u8,
+	pub minor: u8,
+	pub patch: u8,
+	/// The build number, or 0 if unknown
+	pub build: u16,
+	/// Whether this is the CAS version of the OS
+	pub cas: bool,
+	pub model: Model,
+}
+
+impl OsVersion {
+	/// Creates a version with an unknown build number, for use in tables passed
+	/// to [`select`].
+	pub const fn new(major: u8, minor: u8, patch: u8, cas: bool, model: Model) -> Self {
+		OsVersion {
+			major,
+			minor,
+			patch,
+			build: 0,
+			cas,
+			model,
+		}
+	}
+
+	/// Sets the build number.
+	pub const fn with_build(mut self, build: u16) -> Self {
+		self.build = build;
+		self
+	}
+
+	/// Returns `true` if this version is the same as or newer than
+	/// `major.minor.patch`.
+	pub fn at_least(&self, major: u8, minor: u8, patch: u8) -> bool {
+		(self.major, self.minor, self.patch) >= (major, minor, patch)
+	}
+
+	/// Returns `true` if both versions are the same release for the same
+	/// model, ignoring the build number.
+	pub fn same_release(&self, other: &OsVersion) -> bool {
+		(self.major, self.minor, self.patch, self.cas, self.model)
+			== (
+				other.major,
+				other.minor,
+				other.patch,
+				other.cas,
+				other.model,
+			)
+	}
+}
+
+impl fmt::Display for OsVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let model = match self.model {
+			Model::Classic | Model::Other => "",
+			Model::Cm => "CM ",
+			Model::Cx => "CX ",
+		};
+		let cas = if self.cas { "CAS " } else { "" };
+		write!(
+			f,
+			"{}{}{}.{}.{}",
+			model, cas, self.major, self.minor, self.patch
+		)?;
+		if self.build != 0 {
+			write!(f, ".{}", self.build)?;
+		}
+		Ok(())
+	}
+}
+
+/// The OS versions supported by `nl_osvalue`, in the order that Ndless uses
+/// for its `values[]` parameter: two entries, non-CAS then CAS, per model and
+/// release, up to OS 4.5.1 on the CX. Ndless supports newer versions, such as
+/// later 4.5 releases and the CX II, but the order of their entries isn't
+/// listed here, so they are reported as unknown rather than guessed.
+const VERSIONS: [OsVersion; 32] = {
+	use Model::*;
+	[
+		OsVersion::new(3, 1, 0, false, Classic).with_build(392),
+		OsVersion::new(3, 1, 0, true, Classic).with_build(392),
+		OsVersion::new(3, 1, 0, false, Cx).with_build(392),
+		OsVersion::new(3, 1, 0, true, Cx).with_build(392),
+		OsVersion::new(3, 1, 0, false, Cm).with_build(392),
+		OsVersion::new(3, 1, 0, true, Cm).with_build(392),
+		OsVersion::new(3, 6, 0, false, Classic).with_build(546),
+		OsVersion::new(3, 6, 0, true, Classic).with_build(550),
+		OsVersion::new(3, 6, 0, false, Cx).with_build(546),
+		OsVersion::new(3, 6, 0, true, Cx).with_build(550),
+		OsVersion::new(3, 9, 0, false, Classic).with_build(461),
+		OsVersion::new(3, 9, 0, true, Classic).with_build(463),
+		OsVersion::new(3, 9, 0, false, Cx).with_build(461),
+		OsVersion::new(3, 9, 0, true, Cx).with_build(463),
+		OsVersion::new(3, 9, 1, false, Classic).with_build(38),
+		OsVersion::new(3, 9, 1, true, Classic).with_build(38),
+		OsVersion::new(3, 9, 1, false, Cx).with_build(38),
+		OsVersion::new(3, 9, 1, true, Cx).with_build(38),
+		OsVersion::new(4, 0, 0, false, Cx).with_build(235),
+		OsVersion::new(4, 0, 0, true, Cx).with_build(235),
+		OsVersion::new(4, 0, 3, false, Cx).with_build(29),
+		OsVersion::new(4, 0, 3, true, Cx).with_build(29),
+		OsVersion::new(4, 2, 0, false, Cx).with_build(532),
+		OsVersion::new(4, 2, 0, true, Cx).with_build(532),
+		OsVersion::new(4, 3, 0, false, Cx).with_build(702),
+		OsVersion::new(4, 3, 0, true, Cx).with_build(702),
+		OsVersion::new(4, 4, 0, false, Cx).with_build(532),
+		OsVersion::new(4, 4, 0, true, Cx).with_build(532),
+		OsVersion::new(4, 5, 0, false, Cx).with_build(1180),
+		OsVersion::new(4, 5, 0, true, Cx).with_build(1180),
+		OsVersion::new(4, 5, 1, false, Cx).with_build(12),
+		OsVersion::new(4, 5, 1, true, Cx).with_build(12),
+	]
+};
+
+/// Returns the index into `nl_osvalue`'s `values[]` array for the running OS,
+/// or `None` if it isn't one of [`VERSIONS`].
+fn version_index() -> Option<usize> {
+	let mut values = [0u32; VERSIONS.len()];
+	for (i, value) in values.iter_mut().enumerate() {
+		*value = i as u32 + 1;
+	}
+	match unsafe { ndless_sys::nl_osvalue(values.as_ptr(), values.len() as u32) } {
+		0 => None,
+		index => Some(index as usize - 1),
+	}
+}
+
+/// Returns the version of the running OS, or `None` if it is newer than the
+/// versions known by this library, which go up to OS 4.5.1. Programs that
+/// need to run on newer OS versions should treat `None` as "newer than 4.5.1"
+/// rather than as unsupported, and check [`ndless_revision`] for features of
+/// Ndless itself.
+pub fn version() -> Option<OsVersion> {
+	version_index().and_then(|index| VERSIONS.get(index).copied())
+}
+
+/// Returns the revision of Ndless that is installed, such as `2015` for
+/// v4.5.0 r2015.
+pub fn ndless_revision() -> u32 {
+	unsafe { ndless_sys::nl_ndless_rev() }
+}
+
+/// Selects the value in `table` corresponding to the running OS. This is a
+/// typed replacement for `nl_osvalue`'s positional array.
+///
+/// Build numbers are ignored. Returns `None` if the running OS is unknown or is
+/// not part of the table.
+///
+/// ```
+/// use ndless::hw::Model;
+/// use ndless::os::{self, OsVersion};
+///
+/// const SYSCALL_ADDR: &[(OsVersion, u32)] = &[
+/// 	(OsVersion::new(3, 6, 0, false, Model::Cx), 0x1000_0000),
+/// 	(OsVersion::new(3, 6, 0, true, Model::Cx), 0x1000_0100),
+/// ];
+///
+/// match os::select(SYSCALL_ADDR) {
+/// 	Some(addr) => { /* ... */ }
+/// 	None => ndless::msg::msg("Unsupported OS", "This program requires OS 3.6 on a CX"),
+/// }
+/// ```
+pub fn select<T>(table: &[(OsVersion, T)]) -> Option<&T> {
+	let current = version()?;
+	table
+		.iter()
+		.find(|(version, _)| version.same_release(&current))
+		.map(|(_, value)| value)
+}
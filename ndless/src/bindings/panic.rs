@@ -0,0 +1,131 @@
+//! # Unwinding
+//! With the `unwind` feature of `ndless-handler`, which enables the one of this
+//! crate, panics unwind the stack like on desktop platforms: destructors run,
+//! so files are closed and screen modes restored, and panics may be caught with
+//! [`catch_unwind`]. [`process::exit_gracefully`] also uses unwinding to exit
+//! from anywhere in the program while running destructors. This module is only
+//! available with the feature.
+//!
+//! Unwinding uses the ARM exception handling tables generated by the compiler
+//! and the unwinder of `libgcc`, which is linked by the Ndless toolchain.
+//!
+//! The payload of a panic is a [`CrashInfo`], which may be retrieved with
+//! [`downcast`][Box::downcast]:
+//!
+//! ```
+//! use ndless::crash::CrashInfo;
+//! use ndless::panic;
+//!
+//! let result = panic::catch_unwind(|| {
+//! 	panic!("oops");
+//! });
+//! if let Err(payload) = result {
+//! 	if let Ok(info) = payload.downcast::<CrashInfo>() {
+//! 		ndless::msg::msg("Recovered from a panic", &info.to_string());
+//! 	}
+//! }
+//! ```
+//!
+//! Uncaught panics are handled as described in [`crash`][crate::crash], after
+//! unwinding to `main`.
+//!
+//! [`process::exit_gracefully`]: crate::process::exit_gracefully
+//! [`CrashInfo`]: crate::crash::CrashInfo
+
+use core::any::Any;
+use core::intrinsics;
+use core::mem::ManuallyDrop;
+
+pub use core::panic::{AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
+
+use crate::prelude::*;
+
+mod eh;
+
+pub use eh::__personality;
+
+/// Identifies exceptions raised by this module, as opposed to C++ exceptions
+const EXCEPTION_CLASS: u64 = u64::from_be_bytes(*b"NDLSRUST");
+
+#[repr(C)]
+struct Exception {
+	header: eh::UnwindException,
+	payload: Box<dyn Any + Send>,
+}
+
+extern "C" fn exception_cleanup(_reason: eh::ReasonCode, exception: *mut eh::UnwindException) {
+	unsafe { drop(Box::from_raw(exception as *mut Exception)) };
+}
+
+/// Starts unwinding with `payload`. Only returns if no frame catches the
+/// exception, e.g. if the program was built without unwinding tables, giving
+/// the payload back.
+pub(crate) fn raise(payload: Box<dyn Any + Send>) -> Box<dyn Any + Send> {
+	let exception = Box::into_raw(Box::new(Exception {
+		header: eh::UnwindException::new(EXCEPTION_CLASS, exception_cleanup),
+		payload,
+	}));
+	unsafe {
+		eh::_Unwind_RaiseException(exception as *mut eh::UnwindException);
+		Box::from_raw(exception).payload
+	}
+}
+
+/// Takes the payload out of a caught exception.
+unsafe fn take_payload(exception: *mut u8) -> Box<dyn Any + Send> {
+	let exception = exception as *mut eh::UnwindException;
+	if (*exception).exception_class != EXCEPTION_CLASS {
+		// A C++ exception, which can't be converted to a payload
+		eh::_Unwind_DeleteException(exception);
+		crate::process::abort();
+	}
+	Box::from_raw(exception as *mut Exception).payload
+}
+
+/// Runs `f`, returning `Err` with the payload if it panics. See the
+/// [module-level documentation][self].
+///
+/// This also catches [`exit_gracefully`][crate::process::exit_gracefully], so
+/// payloads that aren't handled should be passed on with [`resume_unwind`].
+pub fn catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, Box<dyn Any + Send>> {
+	union Data<F, R> {
+		f: ManuallyDrop<F>,
+		result: ManuallyDrop<R>,
+		payload: ManuallyDrop<Box<dyn Any + Send>>,
+	}
+
+	fn call<F: FnOnce() -> R, R>(data: *mut u8) {
+		unsafe {
+			let data = &mut *(data as *mut Data<F, R>);
+			let f = ManuallyDrop::take(&mut data.f);
+			data.result = ManuallyDrop::new(f());
+		}
+	}
+
+	fn catch<F: FnOnce() -> R, R>(data: *mut u8, exception: *mut u8) {
+		unsafe {
+			let data = &mut *(data as *mut Data<F, R>);
+			data.payload = ManuallyDrop::new(take_payload(exception));
+		}
+	}
+
+	let mut data = Data {
+		f: ManuallyDrop::new(f),
+	};
+	unsafe {
+		let data_ptr = &mut data as *mut Data<F, R> as *mut u8;
+		if intrinsics::catch_unwind(call::<F, R>, data_ptr, catch::<F, R>) == 0 {
+			Ok(ManuallyDrop::into_inner(data.result))
+		} else {
+			crate::crash::caught();
+			Err(ManuallyDrop::into_inner(data.payload))
+		}
+	}
+}
+
+/// Continues unwinding with a payload returned by [`catch_unwind`]. The program
+/// is aborted if nothing catches it.
+pub fn resume_unwind(payload: Box<dyn Any + Send>) -> ! {
+	drop(raise(payload));
+	crate::process::abort()
+}
@@ -0,0 +1,319 @@
+//! Personality routine for ARM EHABI, based on the one of `std`, and the
+//! parser of the language-specific data area that it needs. The unwinder
+//! itself is the one of `libgcc`.
+
+use core::mem::size_of;
+use core::ptr;
+
+use cty::{c_int, c_void};
+
+pub type ReasonCode = c_int;
+
+const URC_NO_REASON: ReasonCode = 0;
+const URC_HANDLER_FOUND: ReasonCode = 6;
+const URC_INSTALL_CONTEXT: ReasonCode = 7;
+const URC_CONTINUE_UNWIND: ReasonCode = 8;
+const URC_FAILURE: ReasonCode = 9;
+
+const US_VIRTUAL_UNWIND_FRAME: c_int = 0;
+const US_UNWIND_FRAME_STARTING: c_int = 1;
+const US_UNWIND_FRAME_RESUME: c_int = 2;
+const US_ACTION_MASK: c_int = 3;
+const US_FORCE_UNWIND: c_int = 8;
+
+/// The registers where landing pads expect the exception
+const UNWIND_DATA_REG: (c_int, c_int) = (0, 1);
+/// The scratch register, where GCC personality routines store the exception
+/// for `_Unwind_GetLanguageSpecificData`
+const UNWIND_POINTER_REG: c_int = 12;
+const UNWIND_SP_REG: c_int = 13;
+const UNWIND_IP_REG: c_int = 15;
+
+/// `_UVRSC_CORE`
+const VRS_CORE: c_int = 0;
+/// `_UVRSD_UINT32`
+const VRS_UINT32: c_int = 0;
+
+pub enum UnwindContext {}
+
+/// `_Unwind_Control_Block` of ARM EHABI
+#[repr(C)]
+pub struct UnwindException {
+	pub exception_class: u64,
+	pub exception_cleanup: extern "C" fn(ReasonCode, *mut UnwindException),
+	/// The unwinder, barrier, cleanup, and personality routine caches
+	private: [u32; 20],
+}
+
+impl UnwindException {
+	pub fn new(
+		exception_class: u64,
+		exception_cleanup: extern "C" fn(ReasonCode, *mut UnwindException),
+	) -> Self {
+		UnwindException {
+			exception_class,
+			exception_cleanup,
+			private: [0; 20],
+		}
+	}
+}
+
+extern "C" {
+	pub fn _Unwind_RaiseException(exception: *mut UnwindException) -> ReasonCode;
+	pub fn _Unwind_DeleteException(exception: *mut UnwindException);
+	fn _Unwind_VRS_Get(
+		context: *mut UnwindContext,
+		class: c_int,
+		reg: u32,
+		representation: c_int,
+		value: *mut c_void,
+	) -> c_int;
+	fn _Unwind_VRS_Set(
+		context: *mut UnwindContext,
+		class: c_int,
+		reg: u32,
+		representation: c_int,
+		value: *mut c_void,
+	) -> c_int;
+	fn _Unwind_GetLanguageSpecificData(context: *mut UnwindContext) -> *const u8;
+	fn _Unwind_GetRegionStart(context: *mut UnwindContext) -> usize;
+	fn __gnu_unwind_frame(
+		exception: *mut UnwindException,
+		context: *mut UnwindContext,
+	) -> ReasonCode;
+}
+
+unsafe fn get_gr(context: *mut UnwindContext, reg: c_int) -> usize {
+	let mut value = 0usize;
+	_Unwind_VRS_Get(
+		context,
+		VRS_CORE,
+		reg as u32,
+		VRS_UINT32,
+		&mut value as *mut usize as *mut c_void,
+	);
+	value
+}
+
+unsafe fn set_gr(context: *mut UnwindContext, reg: c_int, mut value: usize) {
+	_Unwind_VRS_Set(
+		context,
+		VRS_CORE,
+		reg as u32,
+		VRS_UINT32,
+		&mut value as *mut usize as *mut c_void,
+	);
+}
+
+/// The personality routine of Rust functions. Called by the `eh_personality`
+/// of `ndless-handler`.
+#[doc(hidden)]
+pub unsafe extern "C" fn __personality(
+	state: c_int,
+	exception: *mut c_void,
+	context: *mut c_void,
+) -> c_int {
+	let exception = exception as *mut UnwindException;
+	let context = context as *mut UnwindContext;
+	let search_phase = match state & US_ACTION_MASK {
+		US_VIRTUAL_UNWIND_FRAME if state & US_FORCE_UNWIND != 0 => {
+			return continue_unwind(exception, context)
+		}
+		US_VIRTUAL_UNWIND_FRAME => true,
+		US_UNWIND_FRAME_STARTING => false,
+		US_UNWIND_FRAME_RESUME => return continue_unwind(exception, context),
+		_ => return URC_FAILURE,
+	};
+	set_gr(context, UNWIND_POINTER_REG, exception as usize);
+	let action = match find_action(context) {
+		Some(action) => action,
+		None => return URC_FAILURE,
+	};
+	match action {
+		Action::None => continue_unwind(exception, context),
+		Action::Cleanup(_) if search_phase => continue_unwind(exception, context),
+		Action::Catch(_) if search_phase => {
+			// The SP in the barrier cache must be updated when a handler is found
+			(*exception).private[5] = get_gr(context, UNWIND_SP_REG) as u32;
+			URC_HANDLER_FOUND
+		}
+		Action::Cleanup(landing_pad) | Action::Catch(landing_pad) => {
+			set_gr(context, UNWIND_DATA_REG.0, exception as usize);
+			set_gr(context, UNWIND_DATA_REG.1, 0);
+			let thumb = get_gr(context, UNWIND_IP_REG) & 1;
+			set_gr(context, UNWIND_IP_REG, landing_pad | thumb);
+			URC_INSTALL_CONTEXT
+		}
+		Action::Terminate => URC_FAILURE,
+	}
+}
+
+/// On ARM EHABI, the personality routine unwinds the frame itself.
+unsafe fn continue_unwind(
+	exception: *mut UnwindException,
+	context: *mut UnwindContext,
+) -> ReasonCode {
+	if __gnu_unwind_frame(exception, context) == URC_NO_REASON {
+		URC_CONTINUE_UNWIND
+	} else {
+		URC_FAILURE
+	}
+}
+
+enum Action {
+	None,
+	Cleanup(usize),
+	Catch(usize),
+	Terminate,
+}
+
+const DW_EH_PE_OMIT: u8 = 0xFF;
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_ULEB128: u8 = 0x01;
+const DW_EH_PE_UDATA2: u8 = 0x02;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SLEB128: u8 = 0x09;
+const DW_EH_PE_SDATA2: u8 = 0x0A;
+const DW_EH_PE_SDATA4: u8 = 0x0B;
+const DW_EH_PE_SDATA8: u8 = 0x0C;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_FUNCREL: u8 = 0x40;
+const DW_EH_PE_ALIGNED: u8 = 0x50;
+const DW_EH_PE_INDIRECT: u8 = 0x80;
+
+struct Reader(*const u8);
+
+impl Reader {
+	unsafe fn read<T: Copy>(&mut self) -> T {
+		let value = ptr::read_unaligned(self.0 as *const T);
+		self.0 = self.0.add(size_of::<T>());
+		value
+	}
+
+	unsafe fn read_uleb128(&mut self) -> u64 {
+		let mut result = 0;
+		let mut shift = 0;
+		loop {
+			let byte = self.read::<u8>();
+			result |= u64::from(byte & 0x7F) << shift;
+			shift += 7;
+			if byte & 0x80 == 0 {
+				return result;
+			}
+		}
+	}
+
+	unsafe fn read_sleb128(&mut self) -> i64 {
+		let mut result = 0;
+		let mut shift = 0;
+		let mut byte;
+		loop {
+			byte = self.read::<u8>();
+			result |= i64::from(byte & 0x7F) << shift;
+			shift += 7;
+			if byte & 0x80 == 0 {
+				break;
+			}
+		}
+		if shift < 64 && byte & 0x40 != 0 {
+			result |= -1 << shift;
+		}
+		result
+	}
+
+	unsafe fn read_offset(&mut self, encoding: u8) -> Option<usize> {
+		if encoding == DW_EH_PE_OMIT {
+			return None;
+		}
+		Some(match encoding & 0x0F {
+			DW_EH_PE_ABSPTR => self.read::<usize>(),
+			DW_EH_PE_ULEB128 => self.read_uleb128() as usize,
+			DW_EH_PE_UDATA2 => self.read::<u16>() as usize,
+			DW_EH_PE_UDATA4 => self.read::<u32>() as usize,
+			DW_EH_PE_UDATA8 => self.read::<u64>() as usize,
+			DW_EH_PE_SLEB128 => self.read_sleb128() as usize,
+			DW_EH_PE_SDATA2 => self.read::<i16>() as usize,
+			DW_EH_PE_SDATA4 => self.read::<i32>() as usize,
+			DW_EH_PE_SDATA8 => self.read::<i64>() as usize,
+			_ => return None,
+		})
+	}
+
+	unsafe fn read_pointer(&mut self, encoding: u8, func_start: usize) -> Option<usize> {
+		if encoding == DW_EH_PE_ALIGNED {
+			let align = size_of::<usize>();
+			self.0 = ((self.0 as usize + align - 1) & !(align - 1)) as *const u8;
+			return Some(self.read::<usize>());
+		}
+		let base = match encoding & 0x70 {
+			DW_EH_PE_ABSPTR => 0,
+			DW_EH_PE_PCREL => self.0 as usize,
+			DW_EH_PE_FUNCREL => func_start,
+			// Text- and data-relative pointers aren't used on ARM
+			_ => return None,
+		};
+		let mut result = base.wrapping_add(self.read_offset(encoding & 0x0F)?);
+		if encoding & DW_EH_PE_INDIRECT != 0 {
+			result = *(result as *const usize);
+		}
+		Some(result)
+	}
+}
+
+/// Finds what to do in the current frame, from its call-site table
+unsafe fn find_action(context: *mut UnwindContext) -> Option<Action> {
+	let lsda = _Unwind_GetLanguageSpecificData(context);
+	if lsda.is_null() {
+		return Some(Action::None);
+	}
+	let func_start = _Unwind_GetRegionStart(context);
+	// The return address is after the call, which may be the start of the next
+	// call-site range
+	let ip = (get_gr(context, UNWIND_IP_REG) & !1) - 1;
+
+	let mut reader = Reader(lsda);
+	let start_encoding = reader.read::<u8>();
+	let landing_pad_base = if start_encoding != DW_EH_PE_OMIT {
+		reader.read_pointer(start_encoding, func_start)?
+	} else {
+		func_start
+	};
+	// Rust doesn't use the type table
+	if reader.read::<u8>() != DW_EH_PE_OMIT {
+		reader.read_uleb128();
+	}
+	let call_site_encoding = reader.read::<u8>();
+	let call_site_table_len = reader.read_uleb128();
+	let action_table = reader.0.add(call_site_table_len as usize);
+
+	while reader.0 < action_table {
+		let start = reader.read_offset(call_site_encoding)?;
+		let len = reader.read_offset(call_site_encoding)?;
+		let landing_pad = reader.read_offset(call_site_encoding)?;
+		let action = reader.read_uleb128();
+		// The table is sorted by start address
+		if ip < func_start.wrapping_add(start) {
+			break;
+		}
+		if ip < func_start.wrapping_add(start + len) {
+			if landing_pad == 0 {
+				return Some(Action::None);
+			}
+			let landing_pad = landing_pad_base.wrapping_add(landing_pad);
+			if action == 0 {
+				return Some(Action::Cleanup(landing_pad));
+			}
+			let type_index = Reader(action_table.add(action as usize - 1)).read_sleb128();
+			return Some(if type_index == 0 {
+				Action::Cleanup(landing_pad)
+			} else {
+				// `catch_unwind` catches everything, and Rust doesn't use
+				// exception specifications
+				Action::Catch(landing_pad)
+			});
+		}
+	}
+	// The call isn't in the table, so it shouldn't unwind
+	Some(Action::Terminate)
+}
@@ -1,4 +1,42 @@
 use core::fmt;
+use core::ptr::addr_of_mut;
+
+use cstr_core::CString;
+
+use crate::ffi::{OsStr, OsStrExt};
+use crate::fs::File;
+use crate::io::{self, Read};
+use crate::path::{Path, PathBuf};
+use crate::prelude::*;
+use crate::syscall::{require, Syscall};
+
+/// Functions registered with [`at_exit`]
+static mut EXIT_HOOKS: Vec<Box<dyn FnOnce()>> = Vec::new();
+
+/// Registers `hook` to be called when the program exits, whether by returning
+/// from `main`, calling [`exit`] or [`abort`], or panicking. Hooks are called
+/// in reverse order of registration, before this crate restores the state of
+/// the calculator, such as timers and interrupts.
+///
+/// Use it to undo changes that the program made to the system. A hook
+/// shouldn't panic: if it does, the remaining hooks aren't called.
+///
+/// ```
+/// use ndless::process;
+///
+/// process::at_exit(|| ndless::msg::msg("Goodbye", "The program exited"));
+/// ```
+pub fn at_exit(hook: impl FnOnce() + 'static) {
+	crate::interrupt::free(|_| unsafe { (*addr_of_mut!(EXIT_HOOKS)).push(Box::new(hook)) });
+}
+
+/// Calls the hooks registered with [`at_exit`], once each.
+pub(crate) fn run_exit_hooks() {
+	let pop = || crate::interrupt::free(|_| unsafe { (*addr_of_mut!(EXIT_HOOKS)).pop() });
+	while let Some(hook) = pop() {
+		hook();
+	}
+}
 
 /// ## WARNING
 ///
@@ -17,6 +55,8 @@ use core::fmt;
 /// ```
 /// to ensure that no memory leaks.
 pub fn abort() -> ! {
+	crate::__cleanup();
+	crate::release_heap();
 	unsafe { ndless_sys::abort() }
 }
 
@@ -46,10 +86,29 @@ pub fn abort() -> ! {
 /// }
 /// ```
 /// to ensure that no memory leaks.
+///
+/// With the `unwind` feature, [`exit_gracefully`] runs destructors instead.
 pub fn exit(code: i32) -> ! {
+	crate::__cleanup();
+	crate::release_heap();
 	unsafe { ndless_sys::exit(code) }
 }
 
+/// The payload of [`exit_gracefully`]
+#[cfg(feature = "unwind")]
+pub(crate) struct Exit(pub i32);
+
+/// Exits the program with `code` like [`exit`], but runs the destructors of
+/// everything on the stack first, by unwinding to `main`. Requires the `unwind`
+/// feature: otherwise, or if unwinding fails, this is the same as [`exit`].
+///
+/// Unwinding may be stopped by [`catch_unwind`][crate::panic::catch_unwind].
+pub fn exit_gracefully(code: i32) -> ! {
+	#[cfg(feature = "unwind")]
+	crate::panic::raise(Box::new(Exit(code)));
+	exit(code)
+}
+
 /// A trait for implementing arbitrary return types in the `main` function.
 ///
 /// The c-main function only supports to return integers as return type.
@@ -103,3 +162,170 @@ impl<E: fmt::Debug> Termination for Result<!, E> {
 		1
 	}
 }
+
+/// A process builder, used to run other Ndless programs.
+///
+/// Unlike [`std::process::Command`], the current program is paused while the
+/// launched program runs, and continues once it exits.
+///
+/// ```
+/// use ndless::process::Command;
+///
+/// let status = Command::new("/documents/ndless/viewer")
+/// 	.arg("/documents/image.png.tns")
+/// 	.status()
+/// 	.expect("failed to launch the viewer");
+///
+/// println!("viewer exited with: {}", status);
+/// ```
+///
+/// [`std::process::Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+pub struct Command {
+	program: PathBuf,
+	args: Vec<CString>,
+	saw_nul: bool,
+}
+
+impl Command {
+	/// Constructs a new `Command` for launching the program at path `program`.
+	///
+	/// The `.tns` extension is added if it's missing. Relative paths are
+	/// resolved from the [current directory][crate::env::current_dir] when the
+	/// program is run.
+	pub fn new<P: AsRef<Path>>(program: P) -> Command {
+		let mut program = program.as_ref().to_path_buf();
+		if program.extension().is_none_or(|ext| ext != "tns") {
+			let mut name = program.file_name().unwrap_or_default().to_os_string();
+			name.push(".tns");
+			program.set_file_name(name);
+		}
+		Command {
+			program,
+			args: vec![],
+			saw_nul: false,
+		}
+	}
+
+	/// Adds an argument to pass to the program. It will be available in its
+	/// [`args`][crate::env::args], after the program's path.
+	pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command {
+		match CString::new(arg.as_ref().as_bytes()) {
+			Ok(arg) => self.args.push(arg),
+			Err(_) => self.saw_nul = true,
+		}
+		self
+	}
+
+	/// Adds multiple arguments to pass to the program.
+	pub fn args<I, S>(&mut self, args: I) -> &mut Command
+	where
+		I: IntoIterator<Item = S>,
+		S: AsRef<OsStr>,
+	{
+		for arg in args {
+			self.arg(arg);
+		}
+		self
+	}
+
+	/// Returns the path of the program, including the `.tns` extension.
+	pub fn get_program(&self) -> &Path {
+		&self.program
+	}
+
+	/// Runs the program, waiting for it to finish and returning its exit
+	/// status.
+	///
+	/// # Errors
+	///
+	/// Returns an [`Err`] if:
+	///
+	/// * The installed version of Ndless doesn't support launching programs.
+	/// * An argument contains a nul byte.
+	/// * The program doesn't exist.
+	/// * The file isn't a Ndless program.
+	pub fn status(&mut self) -> io::Result<ExitStatus> {
+		require(Syscall::Exec)?;
+		if self.saw_nul {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"nul byte found in provided data",
+			));
+		}
+		let program = crate::env::current_dir()?.join(&self.program);
+		check_program(&program)?;
+		let path = CString::new(program.as_os_str().as_bytes())
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "nul byte found in path"))?;
+		let mut argv = self
+			.args
+			.iter()
+			.map(|arg| arg.as_ptr() as *mut cty::c_char)
+			.collect::<Vec<_>>();
+		let argv_ptr = if argv.is_empty() {
+			core::ptr::null_mut()
+		} else {
+			argv.as_mut_ptr()
+		};
+		let code =
+			unsafe { ndless_sys::nl_exec(path.as_ptr(), argv.len() as cty::c_int, argv_ptr) };
+		Ok(ExitStatus(code))
+	}
+}
+
+impl fmt::Debug for Command {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}", self.program)?;
+		for arg in &self.args {
+			write!(f, " {:?}", arg)?;
+		}
+		Ok(())
+	}
+}
+
+/// Makes sure that `path` is a file starting with the header of a Ndless
+/// program, as launching anything else crashes the calculator.
+fn check_program(path: &Path) -> io::Result<()> {
+	if !crate::fs::metadata(path)?.is_file() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"the program path is not a file",
+		));
+	}
+	let mut header = [0u8; 4];
+	match File::open(path)?.read_exact(&mut header) {
+		Ok(()) if &header == b"PRG\0" || &header == b"Zehn" => Ok(()),
+		Ok(()) => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"the file is not a Ndless program",
+		)),
+		Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"the file is not a Ndless program",
+		)),
+		Err(err) => Err(err),
+	}
+}
+
+/// Describes the result of a program after it has terminated.
+///
+/// Returned by [`Command::status`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+	/// Was termination successful? Signals success if the exit code is 0.
+	pub fn success(&self) -> bool {
+		self.0 == 0
+	}
+
+	/// Returns the exit code of the program.
+	pub fn code(&self) -> i32 {
+		self.0
+	}
+}
+
+impl fmt::Display for ExitStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "exit code: {}", self.0)
+	}
+}
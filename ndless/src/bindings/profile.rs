@@ -0,0 +1,309 @@
+//! # Profiling
+//! This module measures how long parts of a program take, with much less
+//! overhead than printing [`get_ticks`][crate::timer::get_ticks] values.
+//!
+//! Create a span with the [`span!`][crate::span] macro. It measures the time
+//! until it is dropped, usually at the end of the scope:
+//!
+//! ```
+//! use ndless::prelude::*;
+//! use ndless::profile;
+//!
+//! fn draw() {
+//! 	let _s = span!("draw");
+//! 	// ...
+//! }
+//!
+//! for _ in 0..100 {
+//! 	draw();
+//! }
+//! for stats in profile::stats() {
+//! 	println!("{}", stats);
+//! }
+//! ```
+//!
+//! Each span updates its hit count and minimum, average, and maximum durations,
+//! and is recorded in a ring buffer of recent events. The ring buffer can be
+//! saved with [`dump`], and converted to the Chrome trace format on the
+//! computer with `cargo ndless trace`, to be viewed in `chrome://tracing` or
+//! [Perfetto](https://ui.perfetto.dev).
+//!
+//! Profiling is only enabled with the `profile` feature of this crate.
+//! Otherwise, spans compile to nothing, [`stats`] is always empty, and [`dump`]
+//! only writes the header, so the code using them doesn't need to change.
+//!
+//! Durations are measured in [ticks][crate::timer::TICKS_PER_SECOND], so the
+//! resolution is about 30 μs.
+
+use core::fmt;
+
+use crate::io;
+use crate::prelude::*;
+
+/// The first line of [`dump`]'s output, followed by the number of ticks per
+/// second
+pub const DUMP_HEADER: &str = "ndless-profile 1";
+
+/// Statistics of a span, returned by [`stats`]. Durations are in ticks.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct SpanStats {
+	pub name: &'static str,
+	/// The number of times the span was entered
+	pub hits: u32,
+	/// The total time spent in the span
+	pub total: u64,
+	pub min: u32,
+	pub max: u32,
+}
+
+impl SpanStats {
+	/// The average duration of the span, in ticks
+	pub fn avg(&self) -> u32 {
+		if self.hits == 0 {
+			0
+		} else {
+			(self.total / u64::from(self.hits)) as u32
+		}
+	}
+}
+
+impl fmt::Display for SpanStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}: {} hits, min {} avg {} max {} ticks",
+			self.name,
+			self.hits,
+			self.min,
+			self.avg(),
+			self.max
+		)
+	}
+}
+
+/// The location of a [`span!`][crate::span], caching its ID. Created by the
+/// macro.
+#[doc(hidden)]
+pub struct CallSite {
+	#[cfg(feature = "profile")]
+	name: &'static str,
+	#[cfg(feature = "profile")]
+	id: core::sync::atomic::AtomicU32,
+}
+
+impl CallSite {
+	#[allow(unused_variables)]
+	pub const fn new(name: &'static str) -> Self {
+		CallSite {
+			#[cfg(feature = "profile")]
+			name,
+			#[cfg(feature = "profile")]
+			id: core::sync::atomic::AtomicU32::new(u32::MAX),
+		}
+	}
+}
+
+/// A span being measured, created by [`span!`][crate::span]. The measurement
+/// ends when it is dropped.
+#[must_use = "the span ends when it is dropped"]
+pub struct Span {
+	#[cfg(feature = "profile")]
+	id: u32,
+	#[cfg(feature = "profile")]
+	start: u64,
+}
+
+impl Span {
+	#[doc(hidden)]
+	#[inline(always)]
+	#[allow(unused_variables)]
+	pub fn enter(site: &'static CallSite) -> Span {
+		#[cfg(feature = "profile")]
+		{
+			Span {
+				id: imp::id(site),
+				start: crate::timer::get_ticks64(),
+			}
+		}
+		#[cfg(not(feature = "profile"))]
+		{
+			Span {}
+		}
+	}
+}
+
+#[cfg(feature = "profile")]
+impl Drop for Span {
+	fn drop(&mut self) {
+		let end = crate::timer::get_ticks64();
+		imp::record(self.id, self.start, end.saturating_sub(self.start) as u32);
+	}
+}
+
+/// Returns the statistics of every span entered so far, in the order they
+/// were first entered.
+pub fn stats() -> Vec<SpanStats> {
+	#[cfg(feature = "profile")]
+	{
+		imp::with(|profile| profile.stats.clone())
+	}
+	#[cfg(not(feature = "profile"))]
+	{
+		Vec::new()
+	}
+}
+
+/// Clears the statistics and the ring buffer.
+pub fn reset() {
+	#[cfg(feature = "profile")]
+	imp::with(|profile| {
+		for stats in &mut profile.stats {
+			*stats = SpanStats {
+				name: stats.name,
+				hits: 0,
+				total: 0,
+				min: 0,
+				max: 0,
+			};
+		}
+		profile.events.clear();
+		profile.next = 0;
+	})
+}
+
+/// Sets the number of events kept in the ring buffer, which is 4096 by
+/// default. Older events are discarded when it is full. The buffer is cleared.
+pub fn set_capacity(capacity: usize) {
+	#[cfg(feature = "profile")]
+	imp::with(|profile| {
+		profile.capacity = capacity;
+		profile.events = Vec::new();
+		profile.next = 0;
+	});
+	#[cfg(not(feature = "profile"))]
+	let _ = capacity;
+}
+
+/// Writes the events in the ring buffer to `writer`, oldest first. Use
+/// `cargo ndless trace` to convert the output to a Chrome trace.
+///
+/// The format is made of lines of text:
+///
+/// * `ndless-profile 1 <ticks per second>` as a header
+/// * `s <id> <name>` for each span
+/// * `e <id> <start tick> <duration in ticks>` for each event
+///
+/// # Examples
+///
+/// ```
+/// use ndless::fs::File;
+/// use ndless::profile;
+///
+/// profile::dump(File::create("/documents/trace.prof.tns")?)?;
+/// ```
+pub fn dump(mut writer: impl io::Write) -> io::Result<()> {
+	writeln!(writer, "{} {}", DUMP_HEADER, crate::timer::TICKS_PER_SECOND)?;
+	#[cfg(feature = "profile")]
+	{
+		let (stats, events) = imp::with(|profile| {
+			let (newer, older) = profile.events.split_at(profile.next);
+			let events: Vec<_> = older.iter().chain(newer).copied().collect();
+			(profile.stats.clone(), events)
+		});
+		for (id, stats) in stats.iter().enumerate() {
+			writeln!(writer, "s {} {}", id, stats.name)?;
+		}
+		for event in events {
+			writeln!(writer, "e {} {} {}", event.id, event.start, event.duration)?;
+		}
+	}
+	writer.flush()
+}
+
+#[cfg(feature = "profile")]
+mod imp {
+	use core::ptr::addr_of_mut;
+	use core::sync::atomic::Ordering;
+
+	use super::{CallSite, SpanStats};
+	use crate::interrupt;
+	use crate::prelude::*;
+
+	#[derive(Clone, Copy)]
+	pub struct Event {
+		pub id: u32,
+		pub start: u64,
+		pub duration: u32,
+	}
+
+	pub struct Profile {
+		pub stats: Vec<SpanStats>,
+		/// Ring buffer of events. Once it is full, `next` is the oldest event.
+		pub events: Vec<Event>,
+		pub next: usize,
+		pub capacity: usize,
+	}
+
+	static mut PROFILE: Profile = Profile {
+		stats: Vec::new(),
+		events: Vec::new(),
+		next: 0,
+		capacity: 4096,
+	};
+
+	pub fn with<R>(f: impl FnOnce(&mut Profile) -> R) -> R {
+		interrupt::free(|_| f(unsafe { &mut *addr_of_mut!(PROFILE) }))
+	}
+
+	/// Returns the ID of the span at `site`, assigning one the first time
+	pub fn id(site: &'static CallSite) -> u32 {
+		let id = site.id.load(Ordering::Relaxed);
+		if id != u32::MAX {
+			return id;
+		}
+		with(|profile| {
+			let id = match profile
+				.stats
+				.iter()
+				.position(|stats| stats.name == site.name)
+			{
+				Some(id) => id,
+				None => {
+					profile.stats.push(SpanStats {
+						name: site.name,
+						hits: 0,
+						total: 0,
+						min: 0,
+						max: 0,
+					});
+					profile.stats.len() - 1
+				}
+			} as u32;
+			site.id.store(id, Ordering::Relaxed);
+			id
+		})
+	}
+
+	pub fn record(id: u32, start: u64, duration: u32) {
+		with(|profile| {
+			let stats = &mut profile.stats[id as usize];
+			if stats.hits == 0 || duration < stats.min {
+				stats.min = duration;
+			}
+			stats.max = stats.max.max(duration);
+			stats.hits = stats.hits.saturating_add(1);
+			stats.total = stats.total.saturating_add(duration.into());
+			let event = Event {
+				id,
+				start,
+				duration,
+			};
+			if profile.events.len() < profile.capacity {
+				profile.events.push(event);
+			} else if profile.capacity > 0 {
+				profile.events[profile.next] = event;
+				profile.next = (profile.next + 1) % profile.capacity;
+			}
+		})
+	}
+}
@@ -0,0 +1,522 @@
+//! # Resident programs
+//! A resident program stays in memory after it returns, after calling
+//! [`set_resident`], and keeps running through hooks: code of the OS patched to
+//! call the program. This module installs hooks, keeps state shared by all the
+//! hooks of a [`Program`], and finds the copy of the program that is already
+//! resident when it is launched again, e.g. to show its settings or uninstall
+//! it.
+//!
+//! A hook replaces two instructions of the OS at an address, which depends on
+//! the OS version: see [`os::select`][crate::os::select]. The handler is called
+//! with the registers of the OS, which it may change, and the two instructions
+//! run after it returns. Hooks run in the context of the OS, so they should be
+//! short. If a handler panics, the panic is written to the crash log if
+//! [enabled][crate::crash::set_log_file], the hook is disabled, and the OS
+//! continues as if the handler had returned: panics in resident code never
+//! bring down the OS. Destructors aren't run in that case, and memory may be
+//! leaked.
+//!
+//! ```
+//! use ndless::hw::Model;
+//! use ndless::msg::{msg_2b, Button};
+//! use ndless::os::{self, OsVersion};
+//! use ndless::prelude::*;
+//! use ndless::resident::{self, Program};
+//!
+//! /// The number of times the hooked code ran
+//! static PROGRAM: Program<u32> = Program::new("counter", 0);
+//!
+//! const HOOK_ADDRESS: &[(OsVersion, usize)] =
+//! 	&[(OsVersion::new(3, 6, 0, false, Model::Cx), 0x1002_3456)];
+//!
+//! let address = match os::select(HOOK_ADDRESS) {
+//! 	Some(address) => *address,
+//! 	None => return,
+//! };
+//! if let Some(instance) = unsafe { PROGRAM.running_instance(address) } {
+//! 	let count = instance.with(|count| *count);
+//! 	let message = format!("Called {} times", count);
+//! 	if msg_2b("Counter", &message, "Uninstall", "Keep") == Button::One {
+//! 		instance.uninstall();
+//! 	}
+//! 	return;
+//! }
+//! resident::set_resident();
+//! unsafe { PROGRAM.hook(address, |_registers| PROGRAM.with(|count| *count += 1)) }.leak();
+//! ```
+//!
+//! Hooks are uninstalled when the program exits if it isn't resident, and the
+//! memory of a resident program is never freed, even after its hooks are
+//! uninstalled.
+
+use core::arch::global_asm;
+use core::cell::{Cell, UnsafeCell};
+use core::mem;
+use core::ptr::{addr_of, addr_of_mut, null, read_volatile, write_volatile};
+
+use ndless_static_vars::{ProgramState, PROGRAM_STATE};
+
+use crate::hw::clear_cache;
+use crate::interrupt;
+pub use crate::ndless::{is_startup, set_resident, try_set_resident};
+use crate::prelude::*;
+
+/// `ldr pc, [pc, #-4]`, written at the hooked address, followed by the address
+/// to jump to
+const LDR_PC: u32 = 0xE51F_F004;
+/// Identifies trampolines made by this module
+const MAGIC: u32 = 0x4E44_4C48;
+/// The maximum number of hooks installed on top of each other that
+/// [`Program::running_instance`] looks through
+const MAX_CHAIN: usize = 16;
+/// Identifies the layout of [`Program`], changed whenever it changes
+const PROGRAM_VERSION: u32 = 1;
+
+/// Indices of the words of [`__ndless_hook_template`]
+mod template {
+	pub const MAGIC: usize = 0;
+	pub const ID: usize = 1;
+	pub const PROGRAM: usize = 2;
+	pub const HOOK: usize = 3;
+	pub const ENTRY: usize = 4;
+	pub const ORIGINAL: usize = 12;
+	pub const RETURN: usize = 15;
+	pub const CALL: usize = 16;
+	pub const WORDS: usize = 17;
+}
+
+global_asm!(
+	".section .text.__ndless_hook_template, \"ax\", %progbits",
+	".arm",
+	".global __ndless_hook_template",
+	"__ndless_hook_template:",
+	// The header, read by `Program::running_instance`
+	".word 0",
+	".word 0",
+	".word 0",
+	"__ndless_hook_template_hook:",
+	".word 0",
+	// The hooked address jumps here
+	"stmfd sp!, {{r0-r12, lr}}",
+	"mrs r4, cpsr",
+	"mov r0, sp",
+	"ldr r1, __ndless_hook_template_hook",
+	"ldr r12, __ndless_hook_template_call",
+	"blx r12",
+	"msr cpsr_fc, r4",
+	"ldmfd sp!, {{r0-r12, lr}}",
+	// The two instructions replaced by the hook
+	".word 0",
+	".word 0",
+	"ldr pc, __ndless_hook_template_return",
+	"__ndless_hook_template_return:",
+	".word 0",
+	"__ndless_hook_template_call:",
+	".word 0",
+	".section .text.__ndless_resident_call, \"ax\", %progbits",
+	".arm",
+	".global __ndless_resident_call",
+	"__ndless_resident_call:",
+	"stmfd sp!, {{r4-r12, lr}}",
+	// Save the hook and the previous guard, for hooks called from hooks
+	"adr r2, __ndless_resident_guard",
+	"ldr r3, [r2]",
+	"stmfd sp!, {{r1, r3}}",
+	"str sp, [r2]",
+	"bl __ndless_resident_dispatch",
+	"__ndless_resident_return:",
+	"ldmfd sp!, {{r1, r3}}",
+	"adr r2, __ndless_resident_guard",
+	"str r3, [r2]",
+	"ldmfd sp!, {{r4-r12, pc}}",
+	// Called when the handler panics, to return as if it had returned
+	".global __ndless_resident_recover",
+	"__ndless_resident_recover:",
+	"adr r2, __ndless_resident_guard",
+	"ldr sp, [r2]",
+	"b __ndless_resident_return",
+	".global __ndless_resident_guard",
+	"__ndless_resident_guard:",
+	".word 0",
+);
+
+extern "C" {
+	static __ndless_hook_template: [u32; template::WORDS];
+	fn __ndless_resident_call();
+	fn __ndless_resident_recover() -> !;
+	/// The stack pointer of the innermost running handler, pointing to its
+	/// hook, or 0 outside of handlers
+	static mut __ndless_resident_guard: usize;
+}
+
+/// Runs the handler of `hook`, unless it is disabled or already running
+#[no_mangle]
+unsafe extern "C" fn __ndless_resident_dispatch(registers: *mut Registers, hook: *mut HookData) {
+	let hook = &mut *hook;
+	if hook.enabled && !hook.running {
+		hook.running = true;
+		(hook.handler)(&mut *registers);
+		hook.running = false;
+	}
+}
+
+/// The registers of the OS when the hooked address was reached. Changes are
+/// applied when the handler returns.
+#[repr(C)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct Registers {
+	/// `r0` to `r12`
+	pub r: [u32; 13],
+	pub lr: u32,
+}
+
+impl Registers {
+	/// Returns the stack pointer of the OS, which can't be changed.
+	pub fn sp(&self) -> usize {
+		self as *const Self as usize + mem::size_of::<Self>()
+	}
+}
+
+type Handler = Box<dyn FnMut(&mut Registers) + Send>;
+
+struct HookData {
+	handler: Handler,
+	address: usize,
+	trampoline: Box<[u32; template::WORDS]>,
+	enabled: bool,
+	running: bool,
+	/// The states borrowed by the running handler, released if it panics
+	borrows: *const Borrow,
+}
+
+/// A state borrowed with [`Program::with`] in a handler, linked to the ones
+/// borrowed by enclosing calls. Lives on the stack of the handler.
+struct Borrow {
+	borrowed: *const Cell<bool>,
+	outer: *const Borrow,
+}
+
+/// Returns the hook whose handler is running, if any
+fn running_hook() -> Option<*mut HookData> {
+	unsafe {
+		if __ndless_resident_guard == 0 {
+			None
+		} else {
+			Some(*(__ndless_resident_guard as *const *mut HookData))
+		}
+	}
+}
+
+impl HookData {
+	fn entry(&self) -> u32 {
+		&self.trampoline[template::ENTRY] as *const u32 as usize as u32
+	}
+}
+
+/// Installed hooks, by id
+static mut HOOKS: Vec<(u32, *mut HookData)> = Vec::new();
+static mut NEXT_ID: u32 = 0;
+
+/// A hook installed with [`Program::hook`]. The hook is uninstalled when this
+/// is dropped, unless it is [leaked][Hook::leak].
+#[must_use = "the hook is uninstalled when it is dropped"]
+#[derive(Debug)]
+pub struct Hook {
+	id: u32,
+	address: usize,
+}
+
+impl Hook {
+	/// The hooked address
+	pub fn address(&self) -> usize {
+		self.address
+	}
+
+	/// Keeps the hook installed, for resident programs. It is still
+	/// uninstalled when the program exits if it isn't resident, or with
+	/// [`Instance::uninstall`].
+	pub fn leak(self) {
+		mem::forget(self)
+	}
+}
+
+impl Drop for Hook {
+	fn drop(&mut self) {
+		unsafe { uninstall(self.id) }
+	}
+}
+
+unsafe fn install(address: usize, header: [u32; 3], handler: Handler) -> Hook {
+	let mut trampoline = Box::new(*addr_of!(__ndless_hook_template));
+	let patch = address as *mut u32;
+	trampoline[template::MAGIC] = header[0];
+	trampoline[template::ID] = header[1];
+	trampoline[template::PROGRAM] = header[2];
+	trampoline[template::ORIGINAL] = read_volatile(patch);
+	trampoline[template::ORIGINAL + 1] = read_volatile(patch.add(1));
+	trampoline[template::RETURN] = address as u32 + 8;
+	trampoline[template::CALL] = __ndless_resident_call as unsafe extern "C" fn() as usize as u32;
+	let hook = Box::into_raw(Box::new(HookData {
+		handler,
+		address,
+		trampoline,
+		enabled: true,
+		running: false,
+		borrows: null(),
+	}));
+	(*hook).trampoline[template::HOOK] = hook as usize as u32;
+	let hooks = &mut *addr_of_mut!(HOOKS);
+	hooks.reserve(1);
+	interrupt::free(|_| {
+		let id = NEXT_ID;
+		NEXT_ID = id.wrapping_add(1);
+		hooks.push((id, hook));
+		write_volatile(patch, LDR_PC);
+		write_volatile(patch.add(1), (*hook).entry());
+		clear_cache();
+		Hook { id, address }
+	})
+}
+
+/// Restores the instructions replaced by the hook. If another hook was
+/// installed on top of it, the hook is only disabled, because the other one
+/// jumps to it.
+unsafe fn uninstall(id: u32) {
+	let removed = interrupt::free(|_| {
+		let hooks = &mut *addr_of_mut!(HOOKS);
+		let index = hooks.iter().position(|&(hook_id, _)| hook_id == id)?;
+		let hook = &mut *hooks[index].1;
+		hook.enabled = false;
+		let patch = hook.address as *mut u32;
+		let on_top = read_volatile(patch) == LDR_PC && read_volatile(patch.add(1)) == hook.entry();
+		if !on_top || hook.running {
+			return None;
+		}
+		write_volatile(patch, hook.trampoline[template::ORIGINAL]);
+		write_volatile(patch.add(1), hook.trampoline[template::ORIGINAL + 1]);
+		clear_cache();
+		Some(hooks.remove(index).1)
+	});
+	if let Some(hook) = removed {
+		drop(Box::from_raw(hook));
+	}
+}
+
+/// Uninstalls all the hooks of this copy of the program, the most recent
+/// first.
+fn uninstall_all() {
+	let ids: Vec<u32> = unsafe { (*addr_of_mut!(HOOKS)).iter().map(|&(id, _)| id).collect() };
+	for id in ids.into_iter().rev() {
+		unsafe { uninstall(id) };
+	}
+}
+
+/// The start of every [`Program`], whatever the type of its state, checked
+/// before using the state of another copy of the program
+#[repr(C)]
+struct Header {
+	/// [`PROGRAM_VERSION`]
+	version: u32,
+	name: &'static str,
+	/// The size and alignment of the state
+	size: usize,
+	align: usize,
+}
+
+impl Header {
+	/// Returns `true` if `other` is a program with the same name and type of
+	/// state.
+	///
+	/// # Safety
+	///
+	/// `other` must point to readable memory, at least as large as a `Header`
+	/// unless its version is different.
+	unsafe fn matches(&self, other: *const Header) -> bool {
+		read_volatile(addr_of!((*other).version)) == self.version && {
+			let other = &*other;
+			other.size == self.size && other.align == self.align && other.name == self.name
+		}
+	}
+}
+
+/// A resident program, with state shared by its hooks, which should be a
+/// `static`. The state survives the program returning, since the memory of a
+/// resident program isn't freed.
+///
+/// The name and the size and alignment of the state identify the program when
+/// it is launched again, with [`running_instance`][Program::running_instance].
+#[repr(C)]
+pub struct Program<T: 'static> {
+	header: Header,
+	state: UnsafeCell<T>,
+	borrowed: Cell<bool>,
+	uninstall: fn(),
+}
+
+unsafe impl<T: Send> Sync for Program<T> {}
+
+impl<T: 'static> Program<T> {
+	pub const fn new(name: &'static str, state: T) -> Self {
+		Program {
+			header: Header {
+				version: PROGRAM_VERSION,
+				name,
+				size: mem::size_of::<T>(),
+				align: mem::align_of::<T>(),
+			},
+			state: UnsafeCell::new(state),
+			borrowed: Cell::new(false),
+			uninstall: uninstall_all,
+		}
+	}
+
+	/// Runs `f` with the state of the program, with interrupts disabled.
+	///
+	/// If a hook handler panics in `f`, the state is released as the hook
+	/// returns to the OS, and may be left partially modified.
+	///
+	/// # Panics
+	///
+	/// Panics if called from `f`.
+	pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+		interrupt::free(|_| {
+			assert!(!self.borrowed.replace(true), "the state is already in use");
+			// Recorded in the running hook, so that the state is released if the
+			// handler panics
+			let hook = running_hook();
+			let borrow = Borrow {
+				borrowed: &self.borrowed,
+				outer: hook.map_or(null(), |hook| unsafe { (*hook).borrows }),
+			};
+			if let Some(hook) = hook {
+				unsafe { (*hook).borrows = &borrow };
+			}
+			let result = f(unsafe { &mut *self.state.get() });
+			if let Some(hook) = hook {
+				unsafe { (*hook).borrows = borrow.outer };
+			}
+			self.borrowed.set(false);
+			result
+		})
+	}
+
+	/// A hash of the name and the size of the state, to quickly skip the hooks
+	/// of other programs
+	fn id(&self) -> u32 {
+		// FNV-1a
+		let hash = self.header.name.bytes().fold(0x811C_9DC5u32, |hash, byte| {
+			(hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+		});
+		hash ^ mem::size_of::<T>() as u32
+	}
+
+	/// Installs a hook at `address`, calling `handler` when the OS reaches it.
+	/// Hooks may be installed on top of each other, by this program or others.
+	///
+	/// # Safety
+	///
+	/// `address` must be the address of two ARM instructions of the OS that
+	/// don't depend on their address, such as `ldr` relative to `pc`, and that
+	/// no code jumps to the second of.
+	pub unsafe fn hook(
+		&'static self,
+		address: usize,
+		handler: impl FnMut(&mut Registers) + Send + 'static,
+	) -> Hook {
+		let header = [MAGIC, self.id(), self as *const Self as usize as u32];
+		install(address, header, Box::new(handler))
+	}
+
+	/// Returns another copy of this program, which is resident and has a hook
+	/// at `address`, to detect that the program was launched again.
+	///
+	/// # Safety
+	///
+	/// `address` must be readable, such as an address of the OS that the
+	/// program hooks.
+	pub unsafe fn running_instance(&'static self, address: usize) -> Option<Instance<T>> {
+		let (id, own) = (self.id(), self as *const Self as usize as u32);
+		let mut patch = address as *const u32;
+		for _ in 0..MAX_CHAIN {
+			if read_volatile(patch) != LDR_PC {
+				return None;
+			}
+			let entry = read_volatile(patch.add(1)) as usize as *const u32;
+			let trampoline = entry.sub(template::ENTRY);
+			if read_volatile(trampoline.add(template::MAGIC)) != MAGIC {
+				return None;
+			}
+			let program = read_volatile(trampoline.add(template::PROGRAM));
+			if read_volatile(trampoline.add(template::ID)) == id
+				&& program != own
+				&& self.header.matches(program as usize as *const Header)
+			{
+				return Some(Instance {
+					program: &*(program as usize as *const Program<T>),
+				});
+			}
+			// Look at the hook that this one was installed on top of
+			patch = trampoline.add(template::ORIGINAL);
+		}
+		None
+	}
+}
+
+/// Another copy of the program that is resident, returned by
+/// [`Program::running_instance`]
+pub struct Instance<T: 'static> {
+	program: &'static Program<T>,
+}
+
+impl<T: 'static> Instance<T> {
+	/// Runs `f` with the state of the resident copy, like [`Program::with`].
+	pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+		self.program.with(f)
+	}
+
+	/// Uninstalls all the hooks of the resident copy.
+	pub fn uninstall(self) {
+		(self.program.uninstall)()
+	}
+}
+
+/// Returns `true` if [`set_resident`] was called.
+pub fn is_resident() -> bool {
+	unsafe { PROGRAM_STATE == ProgramState::Resident }
+}
+
+/// Returns `true` if a hook handler is running.
+pub(crate) fn in_handler() -> bool {
+	unsafe { __ndless_resident_guard != 0 }
+}
+
+/// Disables the hook whose handler panicked, and returns to the OS as if the
+/// handler had returned.
+pub(crate) fn handler_panicked(info: &crate::crash::CrashInfo) -> ! {
+	unsafe {
+		let hook = &mut **(__ndless_resident_guard as *const *mut HookData);
+		// Release the states that the handler was using, which are otherwise
+		// released when `Program::with` returns
+		let mut borrow = hook.borrows;
+		while !borrow.is_null() {
+			(*(*borrow).borrowed).set(false);
+			borrow = (*borrow).outer;
+		}
+		hook.borrows = null();
+		// The handler may panic again while logging
+		if hook.enabled {
+			hook.enabled = false;
+			crate::crash::log(info);
+		}
+		hook.running = false;
+		__ndless_resident_recover()
+	}
+}
+
+/// Uninstalls the hooks if the program isn't resident
+#[doc(hidden)]
+pub fn __cleanup() {
+	if !is_resident() {
+		uninstall_all();
+	}
+}
@@ -0,0 +1,466 @@
+//! # Serial port
+//! This module drives the UART directly, to talk to a computer or to Firebird's
+//! serial console. [`io::stdin`][crate::io::stdin] reads from it, and
+//! [`println!`][crate::println] writes to it.
+//!
+//! Received bytes are kept in a small buffer until they are read. They are
+//! moved from the hardware to the buffer whenever this module is used, or as
+//! soon as they arrive after calling [`listen`]. Anything received while the
+//! hardware FIFO is full is lost, and reported by [`take_errors`].
+//!
+//! The configuration of the port is restored when the program exits.
+//!
+//! On models whose UART isn't known, such as the CX II, [`configure`] and
+//! [`Serial`] return errors, and the other functions of this module panic: see
+//! [`regs::is_supported`].
+//!
+//! ```
+//! use ndless::io::{Read, Write};
+//! use ndless::serial::{self, Config, Serial};
+//!
+//! serial::configure(Config {
+//! 	baud_rate: 9600,
+//! 	..Config::default()
+//! })?;
+//! let mut port = Serial::new();
+//! port.write_all(b"ping\n")?;
+//! let mut response = [0; 4];
+//! port.read_exact(&mut response)?;
+//! ```
+
+use core::ptr::addr_of_mut;
+
+use crate::hw::regs::{self, Uart};
+use crate::interrupt::{self, AlreadyRegistered, Interrupt, Irq};
+use crate::io;
+
+/// The number of received bytes that are kept until they are read
+pub const RX_BUFFER_SIZE: usize = 256;
+
+/// The clock of the classic UART at the default CPU speed. It is derived from
+/// the bus clock, so it changes with [`set_speed`][crate::hw::cpu::set_speed].
+const CLASSIC_CLOCK_HZ: u32 = 33_000_000;
+/// The clock of the PL011 UART of the CX and newer
+const PL011_CLOCK_HZ: u32 = 12_000_000;
+
+/// The number of bits in each character
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum DataBits {
+	Five,
+	Six,
+	Seven,
+	Eight,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum Parity {
+	None,
+	Odd,
+	Even,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum StopBits {
+	One,
+	Two,
+}
+
+/// The configuration of the serial port, passed to [`configure`]. The default
+/// is 115200 baud, 8 data bits, no parity and one stop bit, like the OS.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct Config {
+	pub baud_rate: u32,
+	pub data_bits: DataBits,
+	pub parity: Parity,
+	pub stop_bits: StopBits,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			baud_rate: 115_200,
+			data_bits: DataBits::Eight,
+			parity: Parity::None,
+			stop_bits: StopBits::One,
+		}
+	}
+}
+
+/// Errors detected while receiving, returned by [`take_errors`]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash, Default)]
+pub struct ErrorFlags {
+	/// Bytes were lost because a buffer was full
+	pub overrun: bool,
+	/// A byte had the wrong parity
+	pub parity: bool,
+	/// A byte didn't have a valid stop bit, usually because of the wrong baud
+	/// rate
+	pub framing: bool,
+	/// The line was held low for longer than a byte
+	pub break_detected: bool,
+}
+
+impl ErrorFlags {
+	/// Returns `true` if any error occurred.
+	pub fn any(&self) -> bool {
+		self.overrun || self.parity || self.framing || self.break_detected
+	}
+
+	fn merge(&mut self, other: ErrorFlags) {
+		self.overrun |= other.overrun;
+		self.parity |= other.parity;
+		self.framing |= other.framing;
+		self.break_detected |= other.break_detected;
+	}
+}
+
+struct RxBuffer {
+	data: [u8; RX_BUFFER_SIZE],
+	start: usize,
+	len: usize,
+	errors: ErrorFlags,
+}
+
+impl RxBuffer {
+	fn pop(&mut self) -> Option<u8> {
+		if self.len == 0 {
+			return None;
+		}
+		let byte = self.data[self.start];
+		self.start = (self.start + 1) % RX_BUFFER_SIZE;
+		self.len -= 1;
+		Some(byte)
+	}
+}
+
+static mut RX: RxBuffer = RxBuffer {
+	data: [0; RX_BUFFER_SIZE],
+	start: 0,
+	len: 0,
+	errors: ErrorFlags {
+		overrun: false,
+		parity: false,
+		framing: false,
+		break_detected: false,
+	},
+};
+
+/// The registers of the UART that the program was started with, saved the
+/// first time the configuration is changed.
+static mut ORIGINAL: Option<[u32; 5]> = None;
+
+/// Returns the registers of the UART, panicking if they aren't known
+fn uart() -> Uart {
+	regs::uart().expect(regs::UNSUPPORTED)
+}
+
+/// Returns an error if the UART of the model isn't known
+fn check_supported() -> io::Result<()> {
+	if regs::is_supported() {
+		Ok(())
+	} else {
+		Err(io::Error::new(io::ErrorKind::Other, regs::UNSUPPORTED))
+	}
+}
+
+/// Runs `f` with the receive buffer, after moving received bytes into it
+fn with_rx<R>(f: impl FnOnce(&mut RxBuffer) -> R) -> R {
+	interrupt::free(|_| {
+		let rx = unsafe { &mut *addr_of_mut!(RX) };
+		while let Some((byte, errors)) = receive() {
+			rx.errors.merge(errors);
+			if rx.len == RX_BUFFER_SIZE {
+				rx.errors.overrun = true;
+			} else {
+				rx.data[(rx.start + rx.len) % RX_BUFFER_SIZE] = byte;
+				rx.len += 1;
+			}
+		}
+		f(rx)
+	})
+}
+
+/// Reads a byte from the hardware FIFO, if there is one
+fn receive() -> Option<(u8, ErrorFlags)> {
+	match uart() {
+		Uart::Pl011(uart) => {
+			if uart.flags().read() & (1 << 4) != 0 {
+				return None;
+			}
+			let data = uart.data().read();
+			let errors = ErrorFlags {
+				framing: data & (1 << 8) != 0,
+				parity: data & (1 << 9) != 0,
+				break_detected: data & (1 << 10) != 0,
+				overrun: data & (1 << 11) != 0,
+			};
+			Some((data as u8, errors))
+		}
+		Uart::Classic(uart) => {
+			let status = uart.line_status().read();
+			if status & 1 == 0 {
+				return None;
+			}
+			let errors = ErrorFlags {
+				overrun: status & (1 << 1) != 0,
+				parity: status & (1 << 2) != 0,
+				framing: status & (1 << 3) != 0,
+				break_detected: status & (1 << 4) != 0,
+			};
+			Some((uart.data().read() as u8, errors))
+		}
+	}
+}
+
+fn save_original() {
+	unsafe {
+		let original = ORIGINAL;
+		if original.is_some() {
+			return;
+		}
+		ORIGINAL = Some(match uart() {
+			Uart::Pl011(uart) => [
+				uart.int_baud_divisor().read(),
+				uart.frac_baud_divisor().read(),
+				uart.line_control().read(),
+				uart.control().read(),
+				uart.int_mask().read(),
+			],
+			Uart::Classic(uart) => {
+				let line_control = uart.line_control().read();
+				let int_enable = uart.int_enable().read();
+				uart.line_control().write(line_control | 0x80);
+				let divisor = [uart.data().read(), uart.int_enable().read()];
+				uart.line_control().write(line_control);
+				[divisor[0], divisor[1], line_control, int_enable, 0]
+			}
+		});
+	}
+}
+
+/// Configures the baud rate and data format of the serial port.
+///
+/// On classic models, the baud rate is computed for the default CPU speed, as
+/// the clock of the UART follows the bus clock. After changing the speed with
+/// [`hw::cpu::set_speed`][crate::hw::cpu::set_speed], the baud rate is wrong
+/// until the default speed is restored, so only communicate at the default
+/// speed. The UART of the CX and newer has its own clock, and isn't affected.
+///
+/// # Errors
+///
+/// Returns an error of kind [`InvalidInput`][io::ErrorKind::InvalidInput] if
+/// the baud rate can't be generated by the hardware, or of kind
+/// [`Other`][io::ErrorKind::Other] if the UART of the model isn't known.
+pub fn configure(config: Config) -> io::Result<()> {
+	check_supported()?;
+	let invalid_baud_rate = || io::Error::new(io::ErrorKind::InvalidInput, "invalid baud rate");
+	if config.baud_rate == 0 {
+		return Err(invalid_baud_rate());
+	}
+	let data_bits = match config.data_bits {
+		DataBits::Five => 0,
+		DataBits::Six => 1,
+		DataBits::Seven => 2,
+		DataBits::Eight => 3,
+	};
+	// Drain the FIFO before changing the format
+	with_rx(|_| ());
+	match uart() {
+		Uart::Pl011(uart) => {
+			let divisor = (u64::from(PL011_CLOCK_HZ) * 4 + u64::from(config.baud_rate) / 2)
+				/ u64::from(config.baud_rate);
+			let int_divisor = divisor >> 6;
+			if int_divisor == 0 || int_divisor > 0xFFFF {
+				return Err(invalid_baud_rate());
+			}
+			let mut line_control = data_bits << 5 | 1 << 4;
+			match config.parity {
+				Parity::None => {}
+				Parity::Odd => line_control |= 1 << 1,
+				Parity::Even => line_control |= 1 << 1 | 1 << 2,
+			}
+			if config.stop_bits == StopBits::Two {
+				line_control |= 1 << 3;
+			}
+			save_original();
+			while uart.flags().read() & (1 << 3) != 0 {}
+			let control = uart.control().read();
+			unsafe {
+				uart.control().write(0);
+				uart.int_baud_divisor().write(int_divisor as u32);
+				uart.frac_baud_divisor().write(divisor as u32 & 0x3F);
+				uart.line_control().write(line_control);
+				uart.control().write(control | 1 | 1 << 8 | 1 << 9);
+			}
+		}
+		Uart::Classic(uart) => {
+			let divisor = (u64::from(CLASSIC_CLOCK_HZ) + u64::from(config.baud_rate) * 8)
+				/ (u64::from(config.baud_rate) * 16);
+			if divisor == 0 || divisor > 0xFFFF {
+				return Err(invalid_baud_rate());
+			}
+			let mut line_control = data_bits;
+			if config.stop_bits == StopBits::Two {
+				line_control |= 1 << 2;
+			}
+			match config.parity {
+				Parity::None => {}
+				Parity::Odd => line_control |= 1 << 3,
+				Parity::Even => line_control |= 1 << 3 | 1 << 4,
+			}
+			save_original();
+			while uart.line_status().read() & (1 << 6) == 0 {}
+			unsafe {
+				uart.line_control().write(0x80);
+				uart.data().write(divisor as u32 & 0xFF);
+				uart.int_enable().write(divisor as u32 >> 8);
+				uart.line_control().write(line_control);
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Writes a byte, waiting until there is room in the transmit FIFO.
+pub fn write_byte(byte: u8) {
+	match uart() {
+		Uart::Pl011(uart) => {
+			while uart.flags().read() & (1 << 5) != 0 {}
+			unsafe { uart.data().write(byte.into()) };
+		}
+		Uart::Classic(uart) => {
+			while uart.line_status().read() & (1 << 5) == 0 {}
+			unsafe { uart.data().write(byte.into()) };
+		}
+	}
+}
+
+/// Returns the next received byte, or `None` if nothing was received. This
+/// never waits.
+pub fn read_byte() -> Option<u8> {
+	with_rx(RxBuffer::pop)
+}
+
+/// Returns the number of received bytes that haven't been read yet.
+pub fn available() -> usize {
+	with_rx(|rx| rx.len)
+}
+
+/// Returns the errors that occurred since the last call, and clears them.
+pub fn take_errors() -> ErrorFlags {
+	with_rx(|rx| core::mem::take(&mut rx.errors))
+}
+
+/// Moves received bytes into the buffer as soon as they arrive, with an
+/// interrupt handler. This prevents losing bytes when they aren't read often
+/// enough. The handler is removed when the returned [`Interrupt`] is dropped.
+pub fn listen() -> Result<Interrupt, AlreadyRegistered> {
+	save_original();
+	let interrupt = interrupt::register(Irq::Uart, || {
+		with_rx(|_| ());
+		if let Some(Uart::Pl011(uart)) = regs::uart() {
+			// Receive and receive timeout interrupts
+			unsafe { uart.int_clear().write(1 << 4 | 1 << 6) };
+		}
+	})?;
+	unsafe {
+		match uart() {
+			Uart::Pl011(uart) => uart.int_mask().modify(|mask| mask | 1 << 4 | 1 << 6),
+			Uart::Classic(uart) => uart.int_enable().modify(|enable| enable | 1),
+		}
+	}
+	Ok(interrupt)
+}
+
+/// Restores the configuration of the serial port that the program was started
+/// with
+#[doc(hidden)]
+pub fn __cleanup() {
+	if let Some(original) = unsafe { ORIGINAL } {
+		unsafe {
+			match uart() {
+				Uart::Pl011(uart) => {
+					uart.control().write(0);
+					uart.int_baud_divisor().write(original[0]);
+					uart.frac_baud_divisor().write(original[1]);
+					uart.line_control().write(original[2]);
+					uart.int_mask().write(original[4]);
+					uart.control().write(original[3]);
+				}
+				Uart::Classic(uart) => {
+					uart.line_control().write(original[2] | 0x80);
+					uart.data().write(original[0]);
+					uart.int_enable().write(original[1]);
+					uart.line_control().write(original[2]);
+					uart.int_enable().write(original[3]);
+				}
+			}
+			ORIGINAL = None;
+		}
+	}
+}
+
+/// A handle to the serial port, implementing [`Read`][io::Read] and
+/// [`Write`][io::Write].
+///
+/// Reads wait until at least one byte is received, unless the handle is set
+/// to non-blocking mode with [`set_nonblocking`][Serial::set_nonblocking].
+#[derive(Debug, Default)]
+pub struct Serial {
+	nonblocking: bool,
+}
+
+impl Serial {
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// In non-blocking mode, reads return an error of kind
+	/// [`WouldBlock`][io::ErrorKind::WouldBlock] instead of waiting when
+	/// nothing was received.
+	pub fn set_nonblocking(&mut self, nonblocking: bool) {
+		self.nonblocking = nonblocking;
+	}
+}
+
+impl io::Read for Serial {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		check_supported()?;
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		loop {
+			let read = with_rx(|rx| {
+				let mut read = 0;
+				while let Some(byte) = buf.get_mut(read).and_then(|_| rx.pop()) {
+					buf[read] = byte;
+					read += 1;
+				}
+				read
+			});
+			if read > 0 {
+				return Ok(read);
+			}
+			if self.nonblocking {
+				return Err(io::ErrorKind::WouldBlock.into());
+			}
+		}
+	}
+}
+
+impl io::Write for Serial {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		check_supported()?;
+		buf.iter().copied().for_each(write_byte);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		check_supported()?;
+		match uart() {
+			Uart::Pl011(uart) => while uart.flags().read() & (1 << 3) != 0 {},
+			Uart::Classic(uart) => while uart.line_status().read() & (1 << 6) == 0 {},
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,156 @@
+//! # Syscall availability
+//! Many functions were added to Ndless over time, and calling one that the
+//! installed version of Ndless doesn't provide crashes the calculator. The
+//! wrappers in this crate check availability themselves and return
+//! [`Unsupported`], but [`is_available`] may be used to find out beforehand,
+//! e.g. to hide a menu entry. Functions that existed before these checks keep
+//! their signature and panic with the [`Unsupported`] message instead, and have
+//! a `try_` variant returning it, such as
+//! [`try_set_resident`][crate::ndless::try_set_resident].
+
+use core::fmt;
+
+use crate::io;
+use crate::os::ndless_revision;
+
+/// Ndless revision that added `_nl_hassyscall`. Before that, only the revision
+/// is checked.
+const HASSYSCALL_REV: u32 = 2004;
+
+/// Flag set in the number of syscalls that are provided by Ndless instead of
+/// the OS
+const SYSCALLS_ISEXT: cty::c_int = 0x20_0000;
+
+/// Syscalls and libndls functions that are not available in every version of
+/// Ndless
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum Syscall {
+	/// `nl_isstartup`, used by [`is_startup`][crate::ndless::is_startup]
+	IsStartup,
+	/// `nl_set_resident`, used by
+	/// [`try_set_resident`][crate::ndless::try_set_resident]
+	SetResident,
+	/// `show_msg_user_input`, used by
+	/// [`try_msg_input`][crate::msg::try_msg_input]
+	MsgUserInput,
+	/// `show_1numeric_input` and `show_2numeric_input`, used by
+	/// [`msg_numeric`][crate::msg::msg_numeric] and
+	/// [`msg_2numeric`][crate::msg::msg_2numeric]
+	NumericInput,
+	/// `nl_no_scr_redraw`, used by
+	/// [`no_scr_redraw`][crate::ndless::no_scr_redraw]
+	NoScrRedraw,
+	/// `nl_loaded_by_3rd_party_loader`, used by
+	/// [`third_party_loader`][crate::ndless::third_party_loader]
+	LoadedBy3rdPartyLoader,
+	/// `cfg_register_fileext`, used by
+	/// [`register_extension`][crate::config::register_extension], and the
+	/// `cfg_open` and `cfg_get` functions reading the configuration it writes,
+	/// used by [`config::get`][crate::config::get]
+	RegisterFileExt,
+	/// `enable_relative_paths`, used by
+	/// [`enable_relative_paths`][crate::env::enable_relative_paths]
+	EnableRelativePaths,
+	/// `nl_hwsubtype`, used by [`try_hw_subtype`][crate::hw::try_hw_subtype]
+	HwSubtype,
+	/// `nl_exec`
+	Exec,
+	/// `get_documents_dir`, used by
+	/// [`get_documents_dir`][crate::env::get_documents_dir]
+	GetDocumentsDir,
+}
+
+impl Syscall {
+	/// The first Ndless revision providing this syscall
+	pub fn required_rev(self) -> u32 {
+		match self {
+			Syscall::IsStartup => 540,
+			Syscall::SetResident => 553,
+			Syscall::MsgUserInput => 607,
+			Syscall::NumericInput => 607,
+			Syscall::NoScrRedraw => 756,
+			Syscall::LoadedBy3rdPartyLoader => 791,
+			Syscall::RegisterFileExt => 797,
+			Syscall::EnableRelativePaths => 820,
+			Syscall::HwSubtype => 863,
+			Syscall::Exec => 877,
+			Syscall::GetDocumentsDir => 2004,
+		}
+	}
+
+	/// The syscall number for syscalls that Ndless implements itself. libndls
+	/// functions don't have one.
+	fn number(self) -> Option<cty::c_int> {
+		let index = match self {
+			Syscall::IsStartup => 3,
+			Syscall::SetResident => 5,
+			Syscall::NoScrRedraw => 7,
+			Syscall::LoadedBy3rdPartyLoader => 8,
+			Syscall::HwSubtype => 9,
+			Syscall::Exec => 10,
+			_ => return None,
+		};
+		Some(SYSCALLS_ISEXT | index)
+	}
+}
+
+/// Returned when a function requires a newer version of Ndless than the one
+/// installed
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct Unsupported {
+	/// The first Ndless revision supporting the function
+	pub required_rev: u32,
+}
+
+impl fmt::Display for Unsupported {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"Ndless revision {} or newer is required, but {} is installed",
+			self.required_rev,
+			ndless_revision()
+		)
+	}
+}
+
+impl crate::error::Error for Unsupported {}
+
+impl From<Unsupported> for io::Error {
+	fn from(err: Unsupported) -> Self {
+		io::Error::new(io::ErrorKind::Other, err)
+	}
+}
+
+/// Returns `true` if `syscall` may be used with the installed version of
+/// Ndless.
+///
+/// ```
+/// use ndless::syscall::{self, Syscall};
+///
+/// if syscall::is_available(Syscall::Exec) {
+/// 	// show the "Launch" menu entry
+/// }
+/// ```
+pub fn is_available(syscall: Syscall) -> bool {
+	let rev = ndless_revision();
+	if rev < syscall.required_rev() {
+		return false;
+	}
+	match syscall.number() {
+		Some(nr) if rev >= HASSYSCALL_REV => unsafe { ndless_sys::_nl_hassyscall(nr) > 0 },
+		_ => true,
+	}
+}
+
+/// Returns an error if `syscall` can't be used with the installed version of
+/// Ndless.
+pub fn require(syscall: Syscall) -> Result<(), Unsupported> {
+	if is_available(syscall) {
+		Ok(())
+	} else {
+		Err(Unsupported {
+			required_rev: syscall.required_rev(),
+		})
+	}
+}
@@ -3,17 +3,20 @@
 use core::time::Duration;
 
 use crate::hw::idle;
-use crate::timer::{configure_sleep, disable_sleep, get_ticks, has_time_passed, Ticks};
+use crate::time::Instant;
+use crate::timer::{disable_sleep, schedule_wakeup, Ticks, TimerHandle};
+
+/// The longest time that a single [`TimerHandle`] is used for, well within the
+/// range of [`get_ticks`][crate::timer::get_ticks]. Longer sleeps are split up.
+const MAX_SLEEP_TICKS: u64 = 1 << 30;
 
 /// Puts the current thread to sleep for at least the specified amount of time.
 ///
 /// The thread may sleep longer than the duration specified due to scheduling
 /// specifics or platform-dependent functionality. It will never sleep less.
 ///
-/// Problems will occur when sleeping for more than 2^31/32768 seconds, which
-/// is about 18 hours.
-///
-/// This function has a resolution of 30 μs.
+/// This function has a resolution of 30 μs, and may be used for arbitrarily
+/// long durations.
 ///
 /// # Examples
 ///
@@ -26,11 +29,36 @@ use crate::timer::{configure_sleep, disable_sleep, get_ticks, has_time_passed, T
 /// thread::sleep(ten_millis);
 /// ```
 pub fn sleep(dur: Duration) {
-	let ticks = dur.as_ticks();
-	let wanted_time = get_ticks().wrapping_add(ticks);
-	configure_sleep(ticks);
-	while !has_time_passed(wanted_time) {
-		idle();
+	sleep_until(Instant::now() + dur)
+}
+
+/// Puts the current thread to sleep until the specified [`Instant`] has been
+/// reached. Returns immediately if it is in the past.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ndless::thread;
+/// use ndless::time::{Duration, Instant};
+///
+/// let mut next_frame = Instant::now();
+/// loop {
+/// 	// draw a frame
+/// 	next_frame += Duration::from_millis(33);
+/// 	thread::sleep_until(next_frame);
+/// }
+/// ```
+pub fn sleep_until(deadline: Instant) {
+	while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+		if remaining == Duration::from_secs(0) {
+			break;
+		}
+		let ticks = remaining.as_ticks64().clamp(1, MAX_SLEEP_TICKS);
+		let timer = TimerHandle::after(ticks as u32);
+		while !timer.has_fired() {
+			schedule_wakeup();
+			idle();
+			disable_sleep();
+		}
 	}
-	disable_sleep();
 }
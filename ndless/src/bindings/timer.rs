@@ -3,93 +3,201 @@
 //! Tools for interacting with low-level timers of the nspire.
 #![allow(clippy::unreadable_literal)]
 
-use core::ptr::{read_volatile, write_volatile};
+use core::ptr::addr_of_mut;
 
 use ndless_static_vars::*;
 
 use crate::hw::has_colors;
+use crate::hw::regs::{self, Timer, TimerId};
+use crate::interrupt;
+use crate::prelude::*;
 use crate::time::Duration;
 
 pub const TICKS_PER_SECOND: u32 = 32768;
 pub const TICKS_PER_MILLISECOND: u32 = 33;
 pub const MICROSECONDS_PER_TICK: u32 = 1000 / TICKS_PER_MILLISECOND;
 
+/// The upper 32 bits of [`get_ticks64`]
+static mut TICKS_HIGH: u32 = 0;
+/// The last value of [`get_ticks`] seen by [`get_ticks64`], to detect when it
+/// wraps around
+static mut LAST_TICKS: u32 = 0;
+
+/// Whether the sleep timer is configured by [`configure_sleep`], so that its
+/// original configuration is only saved once.
+static mut SLEEP_ARMED: bool = false;
+/// Configuration of the tick timer that the program was started with, saved by
+/// [`__init`].
+static mut ORIGINAL_TICKS: Option<(u32, u32, u32)> = None;
+
 #[doc(hidden)]
 pub fn __init() {
+	// Left alone on models whose timers aren't known, until they are used
+	if regs::is_supported() {
+		unsafe {
+			init_ticks();
+			init_sleep();
+		}
+	}
+}
+
+/// Returns the registers of the timer `id`.
+///
+/// # Panics
+///
+/// Panics if the timers of the model aren't known, see
+/// [`regs::is_supported`].
+fn timer(id: TimerId) -> Timer {
+	regs::timer(id).expect(regs::UNSUPPORTED)
+}
+
+unsafe fn init_ticks() {
+	let original = ORIGINAL_TICKS;
+	if original.is_none() {
+		ORIGINAL_TICKS = Some(match timer(TimerId::First) {
+			Timer::Sp804(timer) => (
+				timer.clock_source().read(),
+				timer.control().read(),
+				timer.load().read(),
+			),
+			Timer::Classic(timer) => (timer.divider().read(), timer.control().read(), 0),
+		});
+	}
+	match timer(TimerId::First) {
+		Timer::Sp804(timer) => {
+			timer.clock_source().write(0xA);
+			timer.control().write(0b10000010);
+			START_VALUE = timer.value().read();
+		}
+		Timer::Classic(timer) => {
+			timer.divider().write(1);
+			timer.control().write(0b00001111);
+			timer.value().write(0);
+		}
+	}
+}
+
+/// Restores the configuration of the tick and sleep timers that the program was
+/// started with
+#[doc(hidden)]
+pub fn __cleanup() {
+	disable_sleep();
+	unsafe {
+		if let Some((first, control, load)) = ORIGINAL_TICKS {
+			match timer(TimerId::First) {
+				Timer::Sp804(timer) => {
+					timer.control().write(0);
+					timer.clock_source().write(first);
+					timer.load().write(load);
+					timer.control().write(control);
+				}
+				Timer::Classic(timer) => {
+					timer.divider().write(first);
+					timer.control().write(control);
+				}
+			}
+			ORIGINAL_TICKS = None;
+		}
+	}
+}
+
+/// Re-applies the tick timer configuration, for when it may have been reset,
+/// such as after changing the CPU speed. The tick count continues from where
+/// it was.
+pub(crate) fn resync() {
+	if !regs::is_supported() {
+		return;
+	}
+	let ticks = get_ticks();
 	unsafe {
+		init_ticks();
 		if has_colors() {
-			let value = 0x900C0004 as *mut u32;
-			let control = 0x900C0008 as *mut u32;
-			let clock_source = 0x900C0080 as *mut u32;
-			write_volatile(clock_source, 0xA);
-			write_volatile(control, 0b10000010);
-			START_VALUE = read_volatile(value);
+			START_VALUE = START_VALUE.wrapping_add(ticks);
 		} else {
-			let value = 0x900C0000 as *mut u32;
-			let control = 0x900C0008 as *mut u32;
-			let divider = 0x900C0004 as *mut u32;
-			write_volatile(divider, 1);
-			write_volatile(control, 0b00001111);
-			write_volatile(value, 0);
+			TICK_SUM = ticks;
 		}
-		init_sleep();
 	}
 }
 
 /// Returns the number of ticks since the program started, based on
 /// a 32768Hz timer (i.e. 32768 ticks per second).
+///
+/// # Panics
+///
+/// Panics on models whose timers aren't known, such as the CX II.
 pub fn get_ticks() -> u32 {
 	unsafe {
-		if has_colors() {
-			let value = 0x900C0004 as *mut u32;
-			START_VALUE.wrapping_sub(read_volatile(value))
-		} else {
-			let value = 0x900C0000 as *mut u32;
-			TICK_SUM += read_volatile(value);
-			write_volatile(value, 0);
-			TICK_SUM
+		match timer(TimerId::First) {
+			Timer::Sp804(timer) => START_VALUE.wrapping_sub(timer.value().read()),
+			Timer::Classic(timer) => {
+				TICK_SUM = TICK_SUM.wrapping_add(timer.value().read());
+				timer.value().write(0);
+				TICK_SUM
+			}
 		}
 	}
 }
 
+/// Returns the number of ticks since the program started, like [`get_ticks`],
+/// but as a 64-bit number that doesn't wrap around after 36 hours.
+///
+/// The hardware counter is extended every time this is called, so it must be
+/// called at least once every 36 hours to notice wrap-arounds.
+/// [`Instant::now`][crate::time::Instant::now] calls this function.
+pub fn get_ticks64() -> u64 {
+	interrupt::free(|_| unsafe {
+		let ticks = get_ticks();
+		if ticks < LAST_TICKS {
+			TICKS_HIGH = TICKS_HIGH.wrapping_add(1);
+		}
+		LAST_TICKS = ticks;
+		u64::from(TICKS_HIGH) << 32 | u64::from(ticks)
+	})
+}
+
 fn init_sleep() {
 	unsafe {
-		if has_colors() {
-			let control = 0x900D0008 as *mut u32;
-			let load = 0x900D0000 as *mut u32;
-			let _value = 0x900D0004 as *mut u32;
-			ORIG_CONTROL = read_volatile(control);
-			ORIG_LOAD = read_volatile(load);
-		} else {
-			let _timer = 0x900D0000 as *mut u32;
-			let control = 0x900D0008 as *mut u32;
-			let divider = 0x900D0004 as *mut u32;
-			ORIG_DIVIDER = read_volatile(divider);
-			ORIG_CONTROL = read_volatile(control);
+		match timer(TimerId::Second) {
+			Timer::Sp804(timer) => {
+				ORIG_CONTROL = timer.control().read();
+				ORIG_LOAD = timer.load().read();
+			}
+			Timer::Classic(timer) => {
+				ORIG_DIVIDER = timer.divider().read();
+				ORIG_CONTROL = timer.control().read();
+			}
 		}
 	}
 }
 
 /// Prepares the system for sleep. [`idle`][crate::hw::idle] must be
 /// called to actually sleep.
+///
+/// This overrides the wake up time of other timers: prefer [`TimerHandle`]
+/// with [`schedule_wakeup`].
+///
+/// # Panics
+///
+/// Panics on models whose timers aren't known, such as the CX II.
 pub fn configure_sleep(ticks: u32) {
 	unsafe {
-		init_sleep();
-		if has_colors() {
-			let control = 0x900D0008 as *mut u32;
-			let load = 0x900D0000 as *mut u32;
-			let _value = 0x900D0004 as *mut u32;
-			write_volatile(control, 0);
-			write_volatile(control, 0b01100011);
-			write_volatile(control, 0b11100011);
-			write_volatile(load, ticks);
-		} else {
-			let timer = 0x900D0000 as *mut u32;
-			let control = 0x900D0008 as *mut u32;
-			let divider = 0x900D0004 as *mut u32;
-			write_volatile(control, 0);
-			write_volatile(divider, 1);
-			write_volatile(timer, ticks.max(2u32.pow(16) - 1));
+		let armed = SLEEP_ARMED;
+		if !armed {
+			init_sleep();
+			SLEEP_ARMED = true;
+		}
+		match timer(TimerId::Second) {
+			Timer::Sp804(timer) => {
+				timer.control().write(0);
+				timer.control().write(0b01100011);
+				timer.control().write(0b11100011);
+				timer.load().write(ticks);
+			}
+			Timer::Classic(timer) => {
+				timer.control().write(0);
+				timer.divider().write(1);
+				timer.value().write(ticks.max(2u32.pow(16) - 1));
+			}
 		}
 	}
 }
@@ -97,21 +205,23 @@ pub fn configure_sleep(ticks: u32) {
 /// Resets the sleep timer so it may be used normally.
 pub fn disable_sleep() {
 	unsafe {
-		if has_colors() {
-			let control = 0x900D0008 as *mut u32;
-			let load = 0x900D0000 as *mut u32;
-			let _value = 0x900D0004 as *mut u32;
-			write_volatile(control, 0);
-			write_volatile(control, ORIG_CONTROL & 0b01111111);
-			write_volatile(load, ORIG_LOAD);
-			write_volatile(control, ORIG_CONTROL);
-		} else {
-			let timer = 0x900D0000 as *mut u32;
-			let control = 0x900D0008 as *mut u32;
-			let divider = 0x900D0004 as *mut u32;
-			write_volatile(control, ORIG_CONTROL);
-			write_volatile(divider, ORIG_DIVIDER);
-			write_volatile(timer, 32);
+		let armed = SLEEP_ARMED;
+		if !armed {
+			return;
+		}
+		SLEEP_ARMED = false;
+		match timer(TimerId::Second) {
+			Timer::Sp804(timer) => {
+				timer.control().write(0);
+				timer.control().write(ORIG_CONTROL & 0b01111111);
+				timer.load().write(ORIG_LOAD);
+				timer.control().write(ORIG_CONTROL);
+			}
+			Timer::Classic(timer) => {
+				timer.control().write(ORIG_CONTROL);
+				timer.divider().write(ORIG_DIVIDER);
+				timer.value().write(32);
+			}
 		}
 	}
 }
@@ -123,10 +233,211 @@ pub fn has_time_passed(at_tick: u32) -> bool {
 	get_ticks().wrapping_sub(at_tick).wrapping_add(half_max) >= half_max
 }
 
+/// A deadline of a [`TimerHandle`]
+#[derive(Clone, Copy)]
+struct Deadline {
+	at_tick: u32,
+	period: Option<u32>,
+	cancelled: bool,
+}
+
+/// Deadlines of all [`TimerHandle`]s, indexed by their ID. Freed slots are
+/// reused.
+static mut DEADLINES: Vec<Option<Deadline>> = Vec::new();
+
+/// Runs `f` with the deadlines list, in a critical section so that interrupt
+/// handlers may use existing timers.
+fn with_deadlines<R>(f: impl FnOnce(&mut Vec<Option<Deadline>>) -> R) -> R {
+	interrupt::free(|_| f(unsafe { &mut *addr_of_mut!(DEADLINES) }))
+}
+
+/// A software timer. Any number of timers may exist at once: they are all
+/// multiplexed over the single hardware sleep timer, which is programmed for
+/// the earliest [`next_deadline`] by [`schedule_wakeup`].
+///
+/// A timer is removed when its handle is dropped.
+///
+/// Creating a timer may allocate, so it must not be done in an
+/// [interrupt handler][crate::interrupt]. Handlers may use and drop timers that
+/// were created outside of them.
+///
+/// ```
+/// use ndless::hw::idle;
+/// use ndless::timer::{disable_sleep, schedule_wakeup, TimerHandle, TICKS_PER_SECOND};
+///
+/// let frame = TimerHandle::periodic(TICKS_PER_SECOND / 30);
+/// let timeout = TimerHandle::after(10 * TICKS_PER_SECOND);
+/// while !timeout.has_fired() {
+/// 	if frame.has_fired() {
+/// 		// draw a frame
+/// 	}
+/// 	schedule_wakeup();
+/// 	idle();
+/// 	disable_sleep();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TimerHandle {
+	id: usize,
+}
+
+impl TimerHandle {
+	fn new(deadline: Deadline) -> Self {
+		let id = with_deadlines(
+			|deadlines| match deadlines.iter().position(Option::is_none) {
+				Some(id) => {
+					deadlines[id] = Some(deadline);
+					id
+				}
+				None => {
+					deadlines.push(Some(deadline));
+					deadlines.len() - 1
+				}
+			},
+		);
+		TimerHandle { id }
+	}
+
+	/// Creates a timer that fires once when [`get_ticks`] reaches `at_tick`.
+	/// Problems will occur when it is more than 2^31 ticks in the future, which
+	/// is about 18 hours.
+	pub fn at(at_tick: u32) -> Self {
+		TimerHandle::new(Deadline {
+			at_tick,
+			period: None,
+			cancelled: false,
+		})
+	}
+
+	/// Creates a timer that fires once after the specified number of ticks.
+	pub fn after(ticks: u32) -> Self {
+		TimerHandle::at(get_ticks().wrapping_add(ticks))
+	}
+
+	/// Creates a timer that fires every `period` ticks, starting `period` ticks
+	/// from now.
+	pub fn periodic(period: u32) -> Self {
+		TimerHandle::new(Deadline {
+			at_tick: get_ticks().wrapping_add(period),
+			period: Some(period),
+			cancelled: false,
+		})
+	}
+
+	fn with<R>(&self, f: impl FnOnce(&mut Deadline) -> R) -> R {
+		with_deadlines(|deadlines| f(deadlines[self.id].as_mut().unwrap()))
+	}
+
+	/// Returns the tick that this timer fires at next, or `None` if it was
+	/// cancelled.
+	pub fn deadline(&self) -> Option<u32> {
+		self.with(|deadline| (!deadline.cancelled).then_some(deadline.at_tick))
+	}
+
+	/// Returns the period of a periodic timer.
+	pub fn period(&self) -> Option<u32> {
+		self.with(|deadline| deadline.period)
+	}
+
+	/// Returns `true` if the timer's deadline has passed. For periodic timers,
+	/// this schedules the next deadline, so `true` is only returned once per
+	/// period. If several periods were missed, the next deadline is one period
+	/// from now.
+	pub fn has_fired(&self) -> bool {
+		self.with(|deadline| {
+			if deadline.cancelled || !has_time_passed(deadline.at_tick) {
+				return false;
+			}
+			if let Some(period) = deadline.period {
+				let next = deadline.at_tick.wrapping_add(period);
+				deadline.at_tick = if has_time_passed(next) {
+					get_ticks().wrapping_add(period)
+				} else {
+					next
+				};
+			}
+			true
+		})
+	}
+
+	/// Changes the deadline of the timer to `at_tick`, restarting it if it was
+	/// cancelled. Periodic timers continue with the same period from there.
+	pub fn reschedule_at(&self, at_tick: u32) {
+		self.with(|deadline| {
+			deadline.at_tick = at_tick;
+			deadline.cancelled = false;
+		})
+	}
+
+	/// Changes the deadline of the timer to the specified number of ticks from
+	/// now.
+	pub fn reschedule_ticks(&self, ticks: u32) {
+		self.reschedule_at(get_ticks().wrapping_add(ticks))
+	}
+
+	/// Stops the timer from firing until it is rescheduled.
+	pub fn cancel(&self) {
+		self.with(|deadline| deadline.cancelled = true)
+	}
+}
+
+impl Drop for TimerHandle {
+	fn drop(&mut self) {
+		with_deadlines(|deadlines| {
+			deadlines[self.id] = None;
+			while let Some(None) = deadlines.last() {
+				deadlines.pop();
+			}
+		})
+	}
+}
+
+/// Returns the tick of the earliest deadline of all [`TimerHandle`]s, or
+/// `None` if there are no active timers. The deadline may already have passed.
+pub fn next_deadline() -> Option<u32> {
+	let now = get_ticks();
+	with_deadlines(|deadlines| {
+		deadlines
+			.iter()
+			.flatten()
+			.filter(|deadline| !deadline.cancelled)
+			.min_by_key(|deadline| {
+				deadline
+					.at_tick
+					.wrapping_sub(now)
+					.wrapping_add(2u32.pow(31))
+			})
+			.map(|deadline| deadline.at_tick)
+	})
+}
+
+/// Programs the sleep timer to wake up at [`next_deadline`], returning it. If
+/// that deadline already passed, [`idle`][crate::hw::idle] returns almost
+/// immediately. Call [`disable_sleep`] after waking up.
+///
+/// Returns `None` without programming anything if there are no active timers.
+pub fn schedule_wakeup() -> Option<u32> {
+	let deadline = next_deadline()?;
+	let now = get_ticks();
+	let ticks = if has_time_passed(deadline) {
+		1
+	} else {
+		deadline.wrapping_sub(now).max(1)
+	};
+	configure_sleep(ticks);
+	Some(deadline)
+}
+
 /// Utilities to convert standard Rust [`Duration`]s into Nspire ticks
 pub trait Ticks {
 	fn from_ticks(ticks: u32) -> Self;
 	fn as_ticks(&self) -> u32;
+	/// Like [`from_ticks`][Ticks::from_ticks], for 64-bit tick counts such as
+	/// [`get_ticks64`]
+	fn from_ticks64(ticks: u64) -> Self;
+	/// Like [`as_ticks`][Ticks::as_ticks], without overflowing for durations
+	/// longer than 36 hours. Saturates at [`u64::MAX`].
+	fn as_ticks64(&self) -> u64;
 }
 
 impl Ticks for Duration {
@@ -146,4 +457,18 @@ impl Ticks for Duration {
 			+ self.subsec_millis() as u32 * TICKS_PER_MILLISECOND
 			+ self.subsec_micros() % 1000 / MICROSECONDS_PER_TICK
 	}
+
+	fn from_ticks64(ticks: u64) -> Self {
+		let secs = ticks / u64::from(TICKS_PER_SECOND);
+		let nanos =
+			ticks % u64::from(TICKS_PER_SECOND) * 1_000_000_000 / u64::from(TICKS_PER_SECOND);
+		Duration::new(secs, nanos as u32)
+	}
+
+	fn as_ticks64(&self) -> u64 {
+		let subsec = u64::from(self.subsec_nanos()) * u64::from(TICKS_PER_SECOND) / 1_000_000_000;
+		self.as_secs()
+			.saturating_mul(TICKS_PER_SECOND.into())
+			.saturating_add(subsec)
+	}
 }
@@ -1791,6 +1791,42 @@ pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
 	fs_imp::readdir(path.as_ref()).map(ReadDir)
 }
 
+static mut MODIFIED: bool = false;
+static mut REFRESH_AT_EXIT: bool = true;
+
+/// Returns `true` if the program created, removed, renamed or wrote to files or
+/// directories with this module.
+///
+/// In that case, the document browser of the OS is refreshed when the program
+/// exits, unless disabled with [`set_refresh_at_exit`].
+pub fn modified() -> bool {
+	unsafe { MODIFIED }
+}
+
+/// Sets whether the document browser is refreshed when the program exits, if
+/// it [modified] files, which is the default. Programs that refresh it
+/// themselves with [`refresh_documents`][crate::ndless::refresh_documents] may
+/// disable this.
+pub fn set_refresh_at_exit(refresh: bool) {
+	unsafe { REFRESH_AT_EXIT = refresh };
+}
+
+/// Records that the program changed the file system
+pub(crate) fn mark_modified() {
+	unsafe { MODIFIED = true };
+}
+
+/// Refreshes the document browser if the program modified files
+#[doc(hidden)]
+pub fn __cleanup() {
+	unsafe {
+		if MODIFIED && REFRESH_AT_EXIT {
+			crate::ndless::refresh_documents();
+		}
+		MODIFIED = false;
+	}
+}
+
 impl DirBuilder {
 	/// Creates a new set of options with default mode/security settings for all
 	/// platforms and also non-recursive.
@@ -412,6 +412,122 @@ where
 	write(buf)
 }
 
+/// A handle to the standard input, which reads from the serial port. See
+/// [`serial`][crate::serial] to configure it.
+///
+/// Reads wait until data is received. The data is buffered in memory shared by
+/// all handles, so lines may be read with [`read_line`][Stdin::read_line], or
+/// with [`BufRead`] after [locking][Stdin::lock] the standard input.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ndless::io::{self, BufRead};
+///
+/// let mut input = String::new();
+/// io::stdin().read_line(&mut input)?;
+/// for line in io::stdin().lock().lines() {
+/// 	println!("{}", line?);
+/// }
+/// ```
+pub struct Stdin {
+	_private: (),
+}
+
+/// A locked handle to the standard input, giving access to its buffer through
+/// [`BufRead`]. Returned by [`Stdin::lock`].
+pub struct StdinLock<'a> {
+	inner: super::sys::stdio::Stdin,
+	buffer: &'a mut StdinBuffer,
+}
+
+/// The buffer of the standard input
+struct StdinBuffer {
+	buf: [u8; 128],
+	/// The position of the next byte to read
+	pos: usize,
+	/// The amount of data in `buf`
+	filled: usize,
+}
+
+static mut STDIN_BUFFER: StdinBuffer = StdinBuffer {
+	buf: [0; 128],
+	pos: 0,
+	filled: 0,
+};
+
+/// Whether a [`StdinLock`] exists
+static mut STDIN_LOCKED: bool = false;
+
+/// Returns a handle to the standard input.
+pub fn stdin() -> Stdin {
+	Stdin { _private: () }
+}
+
+impl Stdin {
+	/// Locks the standard input, giving access to its buffer through
+	/// [`BufRead`]. The lock is released when the returned handle is dropped.
+	///
+	/// # Panics
+	///
+	/// Panics if the standard input is already locked. As there are no other
+	/// threads that could release it, waiting would never end.
+	pub fn lock(&self) -> StdinLock<'static> {
+		unsafe {
+			let locked = STDIN_LOCKED;
+			assert!(!locked, "the standard input is already locked");
+			STDIN_LOCKED = true;
+			StdinLock {
+				inner: super::sys::stdio::Stdin::new(),
+				buffer: &mut *ptr::addr_of_mut!(STDIN_BUFFER),
+			}
+		}
+	}
+
+	/// Locks the standard input and reads a line into `buf`. See
+	/// [`BufRead::read_line`].
+	pub fn read_line(&self, buf: &mut String) -> Result<usize> {
+		self.lock().read_line(buf)
+	}
+}
+
+impl Read for Stdin {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		self.lock().read(buf)
+	}
+}
+
+impl Read for StdinLock<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		let available = self.fill_buf()?;
+		let read = cmp::min(available.len(), buf.len());
+		buf[..read].copy_from_slice(&available[..read]);
+		self.consume(read);
+		Ok(read)
+	}
+}
+
+impl BufRead for StdinLock<'_> {
+	fn fill_buf(&mut self) -> Result<&[u8]> {
+		let buffer = &mut *self.buffer;
+		if buffer.pos >= buffer.filled {
+			buffer.filled = self.inner.read(&mut buffer.buf)?;
+			buffer.pos = 0;
+		}
+		Ok(&buffer.buf[buffer.pos..buffer.filled])
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.buffer.pos = cmp::min(self.buffer.pos + amt, self.buffer.filled);
+	}
+}
+
+impl Drop for StdinLock<'_> {
+	fn drop(&mut self) {
+		unsafe { STDIN_LOCKED = false };
+	}
+}
+
 pub struct Stdout {
 	inner: super::sys::stdio::Stdout,
 }
@@ -10,6 +10,7 @@ use ndless_sys::fileno;
 use libc::{c_int, c_long, mode_t};
 use libc::{lseek as lseek64, nuc_stat, readdir as readdir64};
 
+use crate::file_io::fs::mark_modified;
 use crate::file_io::os::unix::prelude::*;
 use crate::file_io::sys::fd::FileDesc;
 use crate::file_io::sys::time::SystemTime;
@@ -304,6 +305,10 @@ impl File {
 		}
 		let fd = cvt_r(|| unsafe { fileno(file_ptr as _) })?;
 		let fd = FileDesc::new(fd);
+		// Opening with `w` or `a` creates or truncates the file
+		if opts.append || (opts.write && (opts.create || !opts.read)) {
+			mark_modified();
+		}
 
 		Ok(File(fd))
 	}
@@ -341,7 +346,8 @@ impl File {
 		return crate::sys::android::ftruncate64(self.0.raw(), size);
 
 		#[cfg(not(target_os = "android"))]
-		return cvt_r(|| unsafe { ftruncate(self.0.raw(), size as c_long) }).map(|_| ());
+		return cvt_r(|| unsafe { ftruncate(self.0.raw(), size as c_long) })
+			.map(|_| mark_modified());
 	}
 
 	pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
@@ -349,7 +355,11 @@ impl File {
 	}
 
 	pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
-		self.0.write(buf)
+		let written = self.0.write(buf)?;
+		if written > 0 {
+			mark_modified();
+		}
+		Ok(written)
 	}
 
 	pub fn flush(&self) -> io::Result<()> {
@@ -391,6 +401,7 @@ impl DirBuilder {
 	pub fn mkdir(&self, p: &Path) -> io::Result<()> {
 		let p = cstr(p)?;
 		cvt(unsafe { libc::mkdir(p.as_ptr(), self.mode) })?;
+		mark_modified();
 		Ok(())
 	}
 
@@ -457,6 +468,7 @@ pub fn readdir(p: &Path) -> io::Result<ReadDir> {
 pub fn unlink(p: &Path) -> io::Result<()> {
 	let p = cstr(p)?;
 	cvt(unsafe { libc::unlink(p.as_ptr()) })?;
+	mark_modified();
 	Ok(())
 }
 
@@ -464,12 +476,14 @@ pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
 	let old = cstr(old)?;
 	let new = cstr(new)?;
 	cvt(unsafe { libc::rename(old.as_ptr(), new.as_ptr()) })?;
+	mark_modified();
 	Ok(())
 }
 
 pub fn rmdir(p: &Path) -> io::Result<()> {
 	let p = cstr(p)?;
 	cvt(unsafe { libc::rmdir(p.as_ptr()) })?;
+	mark_modified();
 	Ok(())
 }
 
@@ -477,6 +491,7 @@ pub fn link(src: &Path, dst: &Path) -> io::Result<()> {
 	let src = cstr(src)?;
 	let dst = cstr(dst)?;
 	cvt(unsafe { libc::link(src.as_ptr(), dst.as_ptr()) })?;
+	mark_modified();
 	Ok(())
 }
 
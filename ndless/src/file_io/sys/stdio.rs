@@ -5,6 +5,20 @@ use crate::libc;
 use super::super::io;
 use super::super::sys::fd::FileDesc;
 
+pub struct Stdin(());
+
+impl Stdin {
+	pub fn new() -> Stdin {
+		Stdin(())
+	}
+}
+
+impl io::Read for Stdin {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		io::Read::read(&mut crate::serial::Serial::new(), buf)
+	}
+}
+
 pub struct Stdout(());
 
 impl Stdout {
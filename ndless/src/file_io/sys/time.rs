@@ -121,9 +121,9 @@ impl Hash for Timespec {
 mod inner {
 	use core::fmt;
 
-	use crate::file_io::sys::cvt;
 	use crate::libc;
 	use crate::time::Duration;
+	use crate::timer::{self, Ticks};
 
 	use super::Timespec;
 
@@ -143,18 +143,12 @@ mod inner {
 
 	impl Instant {
 		pub fn now() -> Instant {
-			use core::ptr;
-
-			let mut s = libc::timeval {
-				tv_sec: 0,
-				tv_usec: 0,
-			};
-			cvt(unsafe { libc::gettimeofday(&mut s, ptr::null_mut()) }).unwrap();
+			let ticks = Duration::from_ticks64(timer::get_ticks64());
 			Instant {
 				t: Timespec {
 					t: libc::timespec {
-						tv_sec: s.tv_sec,
-						tv_nsec: s.tv_usec * 1000,
+						tv_sec: ticks.as_secs() as libc::c_long,
+						tv_nsec: ticks.subsec_nanos() as libc::c_long,
 					},
 				},
 			}
@@ -188,14 +182,7 @@ mod inner {
 
 	impl SystemTime {
 		pub fn now() -> SystemTime {
-			use core::ptr;
-
-			let mut s = libc::timeval {
-				tv_sec: 0,
-				tv_usec: 0,
-			};
-			cvt(unsafe { libc::gettimeofday(&mut s, ptr::null_mut()) }).unwrap();
-			SystemTime::from(s)
+			SystemTime::from(crate::hw::rtc::unix_secs() as libc::c_uint)
 		}
 
 		pub fn sub_time(&self, other: &SystemTime) -> Result<Duration, Duration> {
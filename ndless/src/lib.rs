@@ -10,6 +10,9 @@
 #![feature(core_intrinsics)]
 #![feature(never_type)]
 pub extern crate alloc;
+// Lets `#[derive(FromArgs)]` be tested inside this crate
+#[cfg(test)]
+extern crate self as ndless;
 pub use core::arch::asm;
 pub use bindings::*;
 
@@ -72,6 +75,19 @@ macro_rules! dbg {
     };
 }
 
+/// Measures the time until the returned [`Span`][profile::Span] is dropped,
+/// under the name `$name`, which must be a constant string. See [`profile`]
+/// for more.
+///
+/// Without the `profile` feature, this compiles to nothing.
+#[macro_export]
+macro_rules! span {
+	($name:expr) => {{
+		static SITE: $crate::profile::CallSite = $crate::profile::CallSite::new($name);
+		$crate::profile::Span::enter(&SITE)
+	}};
+}
+
 pub mod prelude {
 	//! # Ndless prelude
 	//! At the top of your code, add
@@ -88,6 +104,7 @@ pub mod prelude {
 	pub use dbg;
 	pub use print;
 	pub use println;
+	pub use span;
 
 	pub use crate::math::Float;
 }
@@ -111,4 +128,64 @@ pub unsafe fn __init(args: &'static [*const cty::c_char]) {
 		.map(path::PathBuf::from)
 		.and_then(|path| path.parent().map(env::set_current_dir));
 	timer::__init();
+	hw::screen::__init();
+}
+
+/// Runs `main`, catching panics and [`process::exit_gracefully`] with the
+/// `unwind` feature, then restores the state of the calculator.
+#[doc(hidden)]
+pub fn __main<T: process::Termination>(main: impl FnOnce() -> T) -> cty::c_int {
+	#[cfg(feature = "unwind")]
+	let code = match panic::catch_unwind(panic::AssertUnwindSafe(main)) {
+		Ok(result) => result.report(),
+		Err(payload) => match payload.downcast::<process::Exit>() {
+			Ok(exit) => exit.0,
+			Err(payload) => match payload.downcast::<crash::CrashInfo>() {
+				Ok(info) => crash::crashed(&info),
+				Err(payload) => panic::resume_unwind(payload),
+			},
+		},
+	};
+	#[cfg(not(feature = "unwind"))]
+	let code = main().report();
+	__cleanup();
+	release_heap();
+	code
+}
+
+static mut RELEASE_HEAP: Option<fn()> = None;
+
+/// Registers a function giving the heap of the global allocator back to the
+/// OS, called by `ndless-handler` when it manages its own heap.
+#[doc(hidden)]
+pub fn __set_heap_release(release: fn()) {
+	unsafe { RELEASE_HEAP = Some(release) };
+}
+
+/// Gives the heap back to the OS, right before the program ends. Nothing may
+/// use memory allocated before this afterwards.
+pub(crate) fn release_heap() {
+	// The hooks of a resident program keep using the heap
+	if resident::is_resident() {
+		return;
+	}
+	if let Some(release) = unsafe { (*core::ptr::addr_of_mut!(RELEASE_HEAP)).take() } {
+		release();
+	}
+}
+
+/// Restores the state of the calculator that was changed by the program. Called
+/// when the program returns or exits.
+#[doc(hidden)]
+pub fn __cleanup() {
+	oom::__cleanup();
+	process::run_exit_hooks();
+	fs::__cleanup();
+	hw::screen::__cleanup();
+	interrupt::__cleanup();
+	serial::__cleanup();
+	resident::__cleanup();
+	hw::cpu::__cleanup();
+	hw::rtc::__cleanup();
+	timer::__cleanup();
 }